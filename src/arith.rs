@@ -0,0 +1,372 @@
+//integer arithmetic for `$((...))`, `((...))`, `let` and `for ((...))`
+use crate::Shell;
+
+//how deeply variables holding expressions may refer to each other
+const MAX_DEPTH: u32 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+//longest operators first
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "**", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "=", "+", "-", "*", "/", "%", "<", ">", "!", "~", "&", "|", "^", "?", ":", "(", ")", ",",
+];
+
+const ASSIGN_OPS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<=", ">>="];
+
+//C precedence, higher binds tighter
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+//`10`, `0x1f`, `017` and `base#digits` with bases up to 64
+fn parse_number(text: &str) -> Result<i64, String> {
+    let too_great = || format!("value too great for base (error token is \"{}\")", text);
+    let (base, digits) = match text.split_once('#') {
+        Some((base, digits)) => match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => return Err(format!("invalid arithmetic base (error token is \"{}\")", text)),
+        },
+        None if text.starts_with("0x") || text.starts_with("0X") => (16, &text[2..]),
+        None if text.len() > 1 && text.starts_with('0') => (8, &text[1..]),
+        None => (10, text),
+    };
+    if digits.is_empty() {
+        return Err(too_great());
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        //past base 36 lower and upper case letters are different digits, then `@` and `_`
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(too_great()),
+        };
+        if digit >= base {
+            return Err(too_great());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut pos = 0;
+    'outer: while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || matches!(chars[pos], '#' | '@' | '_')) {
+                pos += 1;
+            }
+            let number: String = chars[start..pos].iter().collect();
+            tokens.push(Token::Num(parse_number(&number)?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            tokens.push(Token::Name(chars[start..pos].iter().collect()));
+        } else {
+            for op in OPERATORS {
+                let len = op.chars().count();
+                if pos + len <= chars.len() && chars[pos..pos + len].iter().copied().eq(op.chars()) {
+                    tokens.push(Token::Op(op));
+                    pos += len;
+                    continue 'outer;
+                }
+            }
+            let rest: String = chars[pos..].iter().collect();
+            return Err(format!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Assign(&'static str, String, Box<Expr>),
+    //`++x`, `x--` and friends
+    IncDec { name: String, delta: i64, prefix: bool },
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn error_token(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some(Token::Num(n)) => n.to_string(),
+            Some(Token::Name(name)) => name.clone(),
+            Some(Token::Op(op)) => op.to_string(),
+            None => String::new(),
+        }
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            let right = self.parse_assignment()?;
+            expr = Expr::Comma(Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            if ASSIGN_OPS.contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(op, name, Box::new(value)));
+            }
+        }
+        self.parse_conditional()
+    }
+
+    //`cond ? a : b`, right associative
+    fn parse_conditional(&mut self) -> Result<Expr, String> {
+        let cond = self.parse_binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.parse_assignment()?;
+        if self.peek_op() != Some(":") {
+            return Err(format!("syntax error: `:' expected for conditional expression (error token is \"{}\")", self.error_token()));
+        }
+        self.pos += 1;
+        let otherwise = self.parse_assignment()?;
+        Ok(Expr::Conditional(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    //precedence climbing, every binary operator but `**` is left associative
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        while let Some(op) = self.peek_op() {
+            let precedence = match binary_precedence(op) {
+                Some(p) if p >= min_precedence => p,
+                _ => break,
+            };
+            self.pos += 1;
+            let next = if op == "**" { precedence } else { precedence + 1 };
+            let right = self.parse_binary(next)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op @ "-") | Some(op @ "+") | Some(op @ "!") | Some(op @ "~") => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Some(op @ "++") | Some(op @ "--") => {
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Name(name)) => {
+                        let name = name.clone();
+                        self.pos += 1;
+                        Ok(Expr::IncDec { name, delta: if op == "++" { 1 } else { -1 }, prefix: true })
+                    }
+                    _ => Err(format!("syntax error: operand expected (error token is \"{}\")", self.error_token())),
+                }
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let expr = self.parse_primary()?;
+        if let Expr::Var(name) = &expr {
+            if let Some(op @ "++") | Some(op @ "--") = self.peek_op() {
+                self.pos += 1;
+                return Ok(Expr::IncDec { name: name.clone(), delta: if op == "++" { 1 } else { -1 }, prefix: false });
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Num(n)) => {
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                Ok(Expr::Var(name))
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                if self.peek_op() != Some(")") {
+                    return Err(format!("syntax error: `)' expected (error token is \"{}\")", self.error_token()));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            _ => Err(format!("syntax error: operand expected (error token is \"{}\")", self.error_token())),
+        }
+    }
+}
+
+fn apply_binary(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err("exponent less than 0".to_string()),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        _ => unreachable!("unknown binary operator {}", op),
+    })
+}
+
+impl Shell {
+    pub fn eval_arith(&mut self, text: &str) -> Result<i64, String> {
+        self.arith_depth = 0;
+        self.eval_arith_text(text)
+    }
+
+    fn eval_arith_text(&mut self, text: &str) -> Result<i64, String> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Ok(0);
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_comma()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("syntax error in expression (error token is \"{}\")", parser.error_token()));
+        }
+        self.eval_expr(&expr)
+    }
+
+    //a variable's value is itself an expression, so `a=b b=5` makes `a` 5
+    fn arith_var(&mut self, name: &str) -> Result<i64, String> {
+        let value = match self.vars.get(name) {
+            Some(var) => var.value.scalar().unwrap_or_default().trim().to_string(),
+            None => return Ok(0),
+        };
+        if let Ok(n) = value.parse() {
+            return Ok(n);
+        }
+        if self.arith_depth >= MAX_DEPTH {
+            return Err(format!("expression recursion level exceeded (error token is \"{}\")", name));
+        }
+        self.arith_depth += 1;
+        let res = self.eval_arith_text(&value);
+        self.arith_depth -= 1;
+        res
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<i64, String> {
+        match expr {
+            Expr::Num(n) => Ok(*n),
+            Expr::Var(name) => self.arith_var(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval_expr(operand)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Conditional(cond, then, otherwise) => {
+                if self.eval_expr(cond)? != 0 {
+                    self.eval_expr(then)
+                } else {
+                    self.eval_expr(otherwise)
+                }
+            }
+            Expr::Binary("&&", left, right) => {
+                Ok((self.eval_expr(left)? != 0 && self.eval_expr(right)? != 0) as i64)
+            }
+            Expr::Binary("||", left, right) => {
+                Ok((self.eval_expr(left)? != 0 || self.eval_expr(right)? != 0) as i64)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval_expr(left)?;
+                let right = self.eval_expr(right)?;
+                apply_binary(op, left, right)
+            }
+            Expr::Assign(op, name, value) => {
+                let mut value = self.eval_expr(value)?;
+                if *op != "=" {
+                    let old = self.arith_var(name)?;
+                    value = apply_binary(&op[..op.len() - 1], old, value)?;
+                }
+                self.set_var(name, value.to_string());
+                Ok(value)
+            }
+            Expr::IncDec { name, delta, prefix } => {
+                let old = self.arith_var(name)?;
+                let new = old.wrapping_add(*delta);
+                self.set_var(name, new.to_string());
+                Ok(if *prefix { new } else { old })
+            }
+            Expr::Comma(left, right) => {
+                self.eval_expr(left)?;
+                self.eval_expr(right)
+            }
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//indexed and associative arrays, the assignments that fill them and the `declare` builtin
+use std::collections::{BTreeMap, HashMap};
+
+use crate::lexer::{self, Word};
+use crate::parser::{self, Assign};
+use crate::{write_out, Shell, Var};
+
+#[derive(Clone)]
+pub enum Value {
+    Scalar(String),
+    //indexed arrays can have gaps, the elements are listed in order of their index
+    Indexed(BTreeMap<usize, String>),
+    Assoc(BTreeMap<String, String>),
+}
+
+impl Value {
+    //what `$name` is, element 0 for an array
+    pub fn scalar(&self) -> Option<&str> {
+        match self {
+            Value::Scalar(s) => Some(s),
+            Value::Indexed(map) => map.get(&0).map(|s| s.as_str()),
+            Value::Assoc(map) => map.get("0").map(|s| s.as_str()),
+        }
+    }
+
+    pub fn items(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(s) => vec![s.clone()],
+            Value::Indexed(map) => map.values().cloned().collect(),
+            Value::Assoc(map) => map.values().cloned().collect(),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(_) => vec!["0".to_string()],
+            Value::Indexed(map) => map.keys().map(|i| i.to_string()).collect(),
+            Value::Assoc(map) => map.keys().cloned().collect(),
+        }
+    }
+
+    //the index `arr+=(...)` appends at
+    fn next_index(&self) -> usize {
+        match self {
+            Value::Indexed(map) => map.keys().next_back().map_or(0, |i| i + 1),
+            _ => 1,
+        }
+    }
+}
+
+enum Key {
+    Index(usize),
+    Name(String),
+}
+
+//splits `name[subscript]` into the name and the subscript
+pub fn split_subscript(name: &str) -> Option<(&str, &str)> {
+    let (base, rest) = name.split_once('[')?;
+    Some((base, rest.strip_suffix(']')?))
+}
+
+fn double_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$"))
+}
+
+impl Shell {
+    fn is_assoc(&self, name: &str) -> bool {
+        matches!(self.vars.get(name), Some(Var { value: Value::Assoc(_), .. }))
+    }
+
+    //an expanded subscript is a key for associative arrays and arithmetic for the rest,
+    //negative indexes count back from the end
+    fn subscript_key(&mut self, name: &str, subscript: &str) -> Result<Key, String> {
+        if self.is_assoc(name) {
+            if subscript.is_empty() {
+                return Err(format!("{}: bad array subscript", name));
+            }
+            return Ok(Key::Name(subscript.to_string()));
+        }
+        let index = self.eval_arith(subscript).map_err(|e| format!("{}: {}", subscript.trim(), e))?;
+        if index >= 0 {
+            return Ok(Key::Index(index as usize));
+        }
+        let len = self.vars.get(name).map_or(0, |var| var.value.next_index());
+        match len as i64 + index {
+            index if index >= 0 => Ok(Key::Index(index as usize)),
+            _ => Err(format!("{}[{}]: bad array subscript", name, subscript)),
+        }
+    }
+
+    fn expand_subscript(&mut self, subscript: &str) -> String {
+        match lexer::parse_word_text(subscript, &HashMap::new()) {
+            Ok(word) => self.expand_word_to_string(&word),
+            Err(_) => subscript.to_string(),
+        }
+    }
+
+    //the items of `$@`, `$*`, `${name[@]}` and `${name[*]}`, which can expand to several words
+    pub fn list_items(&self, name: &str) -> Option<Vec<String>> {
+        if name == "@" || name == "*" {
+            return Some(self.positional.clone());
+        }
+        let base = name.strip_suffix("[@]").or_else(|| name.strip_suffix("[*]"))?;
+        Some(self.vars.get(base).map(|var| var.value.items()).unwrap_or_default())
+    }
+
+    pub fn array_keys(&self, name: &str) -> Vec<String> {
+        match split_subscript(name) {
+            Some((base, _)) => self.vars.get(base).map(|var| var.value.keys()).unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    //the value of `name[subscript]`, None if the element is unset
+    pub fn get_element(&mut self, name: &str) -> Option<String> {
+        let (base, subscript) = split_subscript(name)?;
+        let subscript = self.expand_subscript(subscript);
+        let key = match self.subscript_key(base, &subscript) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("josh: {}", e);
+                self.expand_failed = true;
+                return None;
+            }
+        };
+        match (&self.vars.get(base)?.value, key) {
+            (Value::Indexed(map), Key::Index(i)) => map.get(&i).cloned(),
+            (Value::Assoc(map), Key::Name(key)) => map.get(&key).cloned(),
+            (Value::Scalar(s), Key::Index(0)) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    //a scalar becomes element 0 of the array it is turned into
+    fn set_element(&mut self, name: &str, key: Key, value: String, append: bool) {
+        let var = self.vars.entry(name.to_string())
+            .or_insert(Var { value: Value::Indexed(BTreeMap::new()), exported: false });
+        if let Value::Scalar(s) = &var.value {
+            var.value = Value::Indexed(BTreeMap::from([(0, s.clone())]));
+        }
+        let element = match (&mut var.value, key) {
+            (Value::Indexed(map), Key::Index(i)) => map.entry(i).or_default(),
+            (Value::Assoc(map), Key::Name(key)) => map.entry(key).or_default(),
+            _ => return,
+        };
+        if append {
+            element.push_str(&value);
+        } else {
+            *element = value;
+        }
+    }
+
+    //`name=(...)` and `name+=(...)`, elements can be given as `[subscript]=value`
+    fn assign_array(&mut self, name: &str, elements: &[Word], append: bool) {
+        let assoc = self.is_assoc(name);
+        if !append || !self.vars.contains_key(name) {
+            let value = if assoc { Value::Assoc(BTreeMap::new()) } else { Value::Indexed(BTreeMap::new()) };
+            let exported = self.vars.get(name).is_some_and(|var| var.exported);
+            self.vars.insert(name.to_string(), Var { value, exported });
+        }
+        let mut next = self.vars[name].value.next_index();
+        for element in elements {
+            if let Some((index, value, append)) = parser::split_subscript(element) {
+                let subscript = self.expand_word_to_string(&index);
+                let value = self.expand_word_to_string(&value);
+                match self.subscript_key(name, &subscript) {
+                    Ok(key) => {
+                        if let Key::Index(i) = key {
+                            next = i + 1;
+                        }
+                        self.set_element(name, key, value, append);
+                    }
+                    Err(e) => {
+                        eprintln!("josh: {}", e);
+                        self.expand_failed = true;
+                    }
+                }
+            } else if assoc {
+                let text = self.expand_word_to_string(element);
+                eprintln!("josh: {}: {}: must use subscript when assigning associative array", name, text);
+                self.expand_failed = true;
+            } else {
+                //elements without a subscript are split and globbed like arguments
+                for field in self.expand_word(element) {
+                    self.set_element(name, Key::Index(next), field, false);
+                    next += 1;
+                }
+            }
+        }
+    }
+
+    //runs an assignment to a variable, an element or a whole array
+    pub fn assign(&mut self, assign: &Assign) {
+        if let Some(elements) = &assign.array {
+            self.assign_array(&assign.name, elements, assign.append);
+            return;
+        }
+        let value = self.expand_word_to_string(&assign.value);
+        match &assign.index {
+            Some(index) => {
+                let subscript = self.expand_word_to_string(index);
+                match self.subscript_key(&assign.name, &subscript) {
+                    Ok(key) => self.set_element(&assign.name, key, value, assign.append),
+                    Err(e) => {
+                        eprintln!("josh: {}", e);
+                        self.expand_failed = true;
+                    }
+                }
+            }
+            None if assign.append => {
+                let old = self.vars.get(&assign.name).and_then(|var| var.value.scalar()).unwrap_or_default();
+                let value = format!("{}{}", old, value);
+                self.set_var(&assign.name, value);
+            }
+            None => self.set_var(&assign.name, value),
+        }
+    }
+
+    fn declaration(&self, name: &str) -> String {
+        let var = &self.vars[name];
+        let mut flags = match var.value {
+            Value::Scalar(_) => String::new(),
+            Value::Indexed(_) => "a".to_string(),
+            Value::Assoc(_) => "A".to_string(),
+        };
+        if var.exported {
+            flags.push('x');
+        }
+        let flags = if flags.is_empty() { "--".to_string() } else { format!("-{}", flags) };
+        let value = match &var.value {
+            Value::Scalar(s) => double_quote(s),
+            Value::Indexed(map) => {
+                let elements: Vec<String> = map.iter().map(|(i, s)| format!("[{}]={}", i, double_quote(s))).collect();
+                format!("({})", elements.join(" "))
+            }
+            Value::Assoc(map) => {
+                let elements: Vec<String> = map.iter().map(|(k, s)| format!("[{}]={}", k, double_quote(s))).collect();
+                format!("({})", elements.join(" "))
+            }
+        };
+        format!("declare {} {}={}\n", flags, name, value)
+    }
+
+    //`declare [-aAxp] [name[=value]...]`, inside a function the variables are local to it
+    pub fn declare_builtin(&mut self, command: &str, argv: &[String]) -> i32 {
+        let mut kind: Option<char> = None;
+        let mut exported = false;
+        let mut print = false;
+        let mut args: Vec<&String> = Vec::new();
+        for arg in argv {
+            match arg.strip_prefix('-') {
+                Some(flags) if !flags.is_empty() && args.is_empty() => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'a' | 'A' => kind = Some(flag),
+                            'x' => exported = true,
+                            'p' => print = true,
+                            _ => {
+                                eprintln!("josh: {}: -{}: invalid option", command, flag);
+                                eprintln!("{}: usage: {} [-aAxp] [name[=value] ...]", command, command);
+                                return 2;
+                            }
+                        }
+                    }
+                }
+                _ => args.push(arg),
+            }
+        }
+        if print || args.is_empty() {
+            let mut names: Vec<&String> = if args.is_empty() { self.vars.keys().collect() } else { args.clone() };
+            names.sort();
+            let mut status = 0;
+            for name in names {
+                if self.vars.contains_key(name.as_str()) {
+                    if write_out(command, &self.declaration(name)) != 0 {
+                        return 1;
+                    }
+                } else {
+                    eprintln!("josh: {}: {}: not found", command, name);
+                    status = 1;
+                }
+            }
+            return status;
+        }
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !parser::is_name(name) {
+                eprintln!("josh: {}: `{}': not a valid identifier", command, arg);
+                status = 1;
+                continue;
+            }
+            if !self.locals.is_empty() {
+                self.make_local(name);
+            }
+            let var = self.vars.entry(name.to_string())
+                .or_insert(Var { value: Value::Scalar(String::new()), exported: false });
+            let converted = match (&var.value, kind) {
+                (Value::Indexed(map), Some('A')) if !map.is_empty() => {
+                    eprintln!("josh: {}: {}: cannot convert indexed to associative array", command, name);
+                    status = 1;
+                    continue;
+                }
+                (Value::Assoc(map), Some('a')) if !map.is_empty() => {
+                    eprintln!("josh: {}: {}: cannot convert associative to indexed array", command, name);
+                    status = 1;
+                    continue;
+                }
+                (Value::Scalar(s), Some('A')) if !s.is_empty() => Some(Value::Assoc(BTreeMap::from([("0".to_string(), s.clone())]))),
+                (Value::Scalar(s), Some('a')) if !s.is_empty() => Some(Value::Indexed(BTreeMap::from([(0, s.clone())]))),
+                (Value::Assoc(_), Some('A')) | (Value::Indexed(_), Some('a')) => None,
+                (_, Some('A')) => Some(Value::Assoc(BTreeMap::new())),
+                (_, Some('a')) => Some(Value::Indexed(BTreeMap::new())),
+                _ => None,
+            };
+            if let Some(converted) = converted {
+                var.value = converted;
+            }
+            var.exported |= exported;
+            if let Some(value) = value {
+                self.set_var(name, value);
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,251 @@
+//conditional expressions, for the `test` and `[` builtins and `[[ ... ]]`
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+use crate::array::Value;
+use crate::glob;
+use crate::parser::CondExpr;
+use crate::sys;
+use crate::{Shell, Var};
+
+pub const UNARY_OPS: &[&str] = &[
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-p", "-r", "-s", "-S", "-t", "-u", "-v", "-w", "-x", "-z",
+];
+
+pub const BINARY_OPS: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+fn is_int_op(op: &str) -> bool {
+    matches!(op, "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge")
+}
+
+fn file_test(op: &str, path: &str) -> bool {
+    if op == "-L" || op == "-h" {
+        return fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink());
+    }
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return false,
+    };
+    let mode = meta.permissions().mode();
+    match op {
+        "-a" | "-e" => true,
+        "-f" => meta.is_file(),
+        "-d" => meta.is_dir(),
+        "-s" => meta.len() > 0,
+        "-b" => meta.file_type().is_block_device(),
+        "-c" => meta.file_type().is_char_device(),
+        "-p" => meta.file_type().is_fifo(),
+        "-S" => meta.file_type().is_socket(),
+        "-u" => mode & 0o4000 != 0,
+        "-g" => mode & 0o2000 != 0,
+        "-k" => mode & 0o1000 != 0,
+        "-r" => sys::access(path, libc::R_OK),
+        "-w" => sys::access(path, libc::W_OK),
+        "-x" => sys::access(path, libc::X_OK),
+        _ => false,
+    }
+}
+
+fn modified(path: &str) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn compare_ints(op: &str, a: i64, b: i64) -> bool {
+    match op {
+        "-eq" => a == b,
+        "-ne" => a != b,
+        "-lt" => a < b,
+        "-le" => a <= b,
+        "-gt" => a > b,
+        _ => a >= b,
+    }
+}
+
+fn parse_int(s: &str) -> Result<i64, String> {
+    s.trim().parse().map_err(|_| format!("{}: integer expression expected", s))
+}
+
+//the binary operators shared by `test` and `[[`
+fn binary_test(op: &str, a: &str, b: &str) -> Result<bool, String> {
+    Ok(match op {
+        "=" | "==" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        ">" => a > b,
+        //a file that exists is newer than one that doesn't
+        "-nt" => match (modified(a), modified(b)) {
+            (Some(a), Some(b)) => a > b,
+            (a, _) => a.is_some(),
+        },
+        "-ot" => match (modified(a), modified(b)) {
+            (Some(a), Some(b)) => a < b,
+            (_, b) => b.is_some(),
+        },
+        "-ef" => match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        },
+        op => compare_ints(op, parse_int(a)?, parse_int(b)?),
+    })
+}
+
+//the arguments of `test` being parsed, which are evaluated as they are read
+struct TestArgs<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> TestArgs<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).copied()
+    }
+
+    fn or(&mut self, shell: &Shell) -> Result<bool, String> {
+        let mut res = self.and(shell)?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            res |= self.and(shell)?;
+        }
+        Ok(res)
+    }
+
+    fn and(&mut self, shell: &Shell) -> Result<bool, String> {
+        let mut res = self.not(shell)?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            res &= self.not(shell)?;
+        }
+        Ok(res)
+    }
+
+    //`! = x` compares `!` with x
+    fn not(&mut self, shell: &Shell) -> Result<bool, String> {
+        let binary = self.peek(1).is_some_and(|op| BINARY_OPS.contains(&op)) && self.peek(2).is_some();
+        if self.peek(0) == Some("!") && self.peek(1).is_some() && !binary {
+            self.pos += 1;
+            return Ok(!self.not(shell)?);
+        }
+        self.primary(shell)
+    }
+
+    fn primary(&mut self, shell: &Shell) -> Result<bool, String> {
+        let arg = match self.peek(0) {
+            Some(arg) => arg,
+            None => return Err("argument expected".to_string()),
+        };
+        if let (Some(op), Some(b)) = (self.peek(1), self.peek(2)) {
+            if BINARY_OPS.contains(&op) {
+                self.pos += 3;
+                return binary_test(op, arg, b);
+            }
+        }
+        if arg == "(" && self.peek(1).is_some() {
+            self.pos += 1;
+            let res = self.or(shell)?;
+            if self.peek(0) != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            self.pos += 1;
+            return Ok(res);
+        }
+        if let Some(operand) = self.peek(1).filter(|_| UNARY_OPS.contains(&arg)) {
+            self.pos += 2;
+            return shell.unary_test(arg, operand);
+        }
+        self.pos += 1;
+        Ok(!arg.is_empty())
+    }
+}
+
+impl Shell {
+    fn unary_test(&self, op: &str, operand: &str) -> Result<bool, String> {
+        Ok(match op {
+            "-z" => operand.is_empty(),
+            "-n" => !operand.is_empty(),
+            "-t" => sys::isatty(parse_int(operand)? as i32),
+            "-v" => self.vars.contains_key(operand),
+            op => file_test(op, operand),
+        })
+    }
+
+    //`test` and `[`, 0 if the expression is true, 1 if it is false and 2 on errors
+    pub fn test_builtin(&mut self, command: &str, argv: &[String]) -> i32 {
+        let mut args: Vec<&str> = argv.iter().map(|arg| arg.as_str()).collect();
+        if command == "[" {
+            if args.last() != Some(&"]") {
+                eprintln!("josh: [: missing `]'");
+                return 2;
+            }
+            args.pop();
+        }
+        if args.is_empty() {
+            return 1;
+        }
+        let mut test = TestArgs { args: &args, pos: 0 };
+        match test.or(self) {
+            Ok(_) if test.pos < args.len() => {
+                eprintln!("josh: {}: too many arguments", command);
+                2
+            }
+            Ok(res) => !res as i32,
+            Err(e) => {
+                eprintln!("josh: {}: {}", command, e);
+                2
+            }
+        }
+    }
+
+    //`=~` leaves the match and its groups in BASH_REMATCH
+    fn regex_test(&mut self, text: &str, regex: &str) -> Result<bool, String> {
+        let regex = regex::Regex::new(regex).map_err(|_| format!("{}: invalid regular expression", regex))?;
+        let mut groups: BTreeMap<usize, String> = BTreeMap::new();
+        if let Some(captures) = regex.captures(text) {
+            for (i, group) in captures.iter().enumerate() {
+                groups.insert(i, group.map(|m| m.as_str().to_string()).unwrap_or_default());
+            }
+        }
+        let matched = !groups.is_empty();
+        self.vars.insert("BASH_REMATCH".to_string(), Var { value: Value::Indexed(groups), exported: false });
+        Ok(matched)
+    }
+
+    //words in `[[` are expanded without splitting or globbing, the right side of `==` and `!=`
+    //is a pattern and the integer operators take arithmetic expressions
+    pub fn eval_cond(&mut self, expr: &CondExpr) -> Result<bool, String> {
+        Ok(match expr {
+            CondExpr::Word(word) => !self.expand_word_to_string(word).is_empty(),
+            CondExpr::Unary(op, word) => {
+                let operand = self.expand_word_to_string(word);
+                self.unary_test(op, &operand)?
+            }
+            CondExpr::Binary(left, op, right) => {
+                let a = self.expand_word_to_string(left);
+                match op.as_str() {
+                    "=" | "==" | "!=" => {
+                        let pattern = self.expand_pattern(right);
+                        glob::matches(&pattern, &a, false) == (op != "!=")
+                    }
+                    "=~" => {
+                        let regex = self.expand_regex(right);
+                        self.regex_test(&a, &regex)?
+                    }
+                    op if is_int_op(op) => {
+                        let a = self.eval_arith(&a).map_err(|e| format!("{}: {}", a.trim(), e))?;
+                        let b = self.eval_arith_word(right)?;
+                        compare_ints(op, a, b)
+                    }
+                    op => {
+                        let b = self.expand_word_to_string(right);
+                        binary_test(op, &a, &b)?
+                    }
+                }
+            }
+            CondExpr::Not(expr) => !self.eval_cond(expr)?,
+            CondExpr::And(left, right) => self.eval_cond(left)? && self.eval_cond(right)?,
+            CondExpr::Or(left, right) => self.eval_cond(left)? || self.eval_cond(right)?,
+        })
+    }
+}
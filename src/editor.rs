@@ -0,0 +1,758 @@
+//the line editor at the interactive prompt, keys are looked up in a keymap of named actions
+//that `bind` can change, vi mode has its own commands on top of them
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use termion::event::Key;
+
+use crate::vi::{self, Vi, ViResult};
+use crate::{write_out, Shell};
+
+//how many killed pieces of text yank and yank-pop can get back
+const KILL_RING_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    AcceptLine,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    //also ends the input when the line is empty and the key is ctrl-d
+    DeleteChar,
+    BackwardDeleteChar,
+    KillLine,
+    UnixLineDiscard,
+    UnixWordRubout,
+    Yank,
+    YankPop,
+    TransposeChars,
+    ClearScreen,
+    Undo,
+    PreviousHistory,
+    NextHistory,
+    Complete,
+    SelfInsert,
+}
+
+//the actions by the names `bind` knows them by, which are readline's
+const ACTIONS: &[(&str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("backward-char", Action::BackwardChar),
+    ("forward-char", Action::ForwardChar),
+    ("backward-word", Action::BackwardWord),
+    ("forward-word", Action::ForwardWord),
+    ("delete-char", Action::DeleteChar),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("kill-line", Action::KillLine),
+    ("unix-line-discard", Action::UnixLineDiscard),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("yank", Action::Yank),
+    ("yank-pop", Action::YankPop),
+    ("transpose-chars", Action::TransposeChars),
+    ("clear-screen", Action::ClearScreen),
+    ("undo", Action::Undo),
+    ("previous-history", Action::PreviousHistory),
+    ("next-history", Action::NextHistory),
+    ("complete", Action::Complete),
+    ("self-insert", Action::SelfInsert),
+];
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS.iter().find(|(_, a)| *a == action).unwrap().0
+}
+
+fn default_keymap() -> HashMap<Key, Action> {
+    HashMap::from([
+        (Key::Char('\n'), Action::AcceptLine),
+        (Key::Char('\t'), Action::Complete),
+        (Key::Ctrl('a'), Action::BeginningOfLine),
+        (Key::Home, Action::BeginningOfLine),
+        (Key::Ctrl('e'), Action::EndOfLine),
+        (Key::End, Action::EndOfLine),
+        (Key::Left, Action::BackwardChar),
+        (Key::Right, Action::ForwardChar),
+        (Key::Alt('b'), Action::BackwardWord),
+        (Key::Alt('f'), Action::ForwardWord),
+        (Key::Ctrl('d'), Action::DeleteChar),
+        (Key::Delete, Action::DeleteChar),
+        (Key::Backspace, Action::BackwardDeleteChar),
+        (Key::Ctrl('k'), Action::KillLine),
+        (Key::Ctrl('u'), Action::UnixLineDiscard),
+        (Key::Ctrl('w'), Action::UnixWordRubout),
+        (Key::Ctrl('y'), Action::Yank),
+        (Key::Alt('y'), Action::YankPop),
+        (Key::Ctrl('t'), Action::TransposeChars),
+        (Key::Ctrl('l'), Action::ClearScreen),
+        //ctrl-_
+        (Key::Ctrl('7'), Action::Undo),
+        (Key::Up, Action::PreviousHistory),
+        (Key::Down, Action::NextHistory),
+    ])
+}
+
+//the escape sequences terminals send for the keys that have no character
+const SPECIAL_KEYS: &[(&str, Key)] = &[
+    ("[A", Key::Up),
+    ("[B", Key::Down),
+    ("[C", Key::Right),
+    ("[D", Key::Left),
+    ("[H", Key::Home),
+    ("[F", Key::End),
+    ("[3~", Key::Delete),
+];
+
+//the key typed with control and c, as the terminal reads it
+fn ctrl_key(c: char) -> Option<Key> {
+    Some(match c.to_ascii_lowercase() {
+        'm' | 'j' => Key::Char('\n'),
+        'i' => Key::Char('\t'),
+        '?' => Key::Backspace,
+        '[' => Key::Esc,
+        c @ 'a'..='z' => Key::Ctrl(c),
+        c @ '\\'..='_' => Key::Ctrl((c as u8 - b'\\' + b'4') as char),
+        _ => return None,
+    })
+}
+
+//a key written the way readline does, like `\C-a`, `\M-f`, `\e[A` or a plain character
+fn parse_key(seq: &str) -> Option<Key> {
+    if let Some(rest) = seq.strip_prefix("\\C-") {
+        let mut chars = rest.chars();
+        let c = chars.next()?;
+        return if chars.as_str().is_empty() || c == '\\' && chars.as_str() == "\\" { ctrl_key(c) } else { None };
+    }
+    if let Some(rest) = seq.strip_prefix("\\M-").or_else(|| seq.strip_prefix("\\e")) {
+        if rest.is_empty() {
+            return Some(Key::Esc);
+        }
+        if let Some((_, key)) = SPECIAL_KEYS.iter().find(|(s, _)| *s == rest) {
+            return Some(*key);
+        }
+        return match parse_key(rest)? {
+            Key::Char(c) => Some(Key::Alt(c)),
+            _ => None,
+        };
+    }
+    match seq {
+        "\\t" => Some(Key::Char('\t')),
+        "\\n" | "\\r" => Some(Key::Char('\n')),
+        "\\\\" => Some(Key::Char('\\')),
+        "\\\"" => Some(Key::Char('"')),
+        "\\'" => Some(Key::Char('\'')),
+        seq => {
+            let mut chars = seq.chars();
+            let c = chars.next()?;
+            if chars.next().is_some() { None } else { Some(Key::Char(c)) }
+        }
+    }
+}
+
+fn format_key(key: Key) -> String {
+    match key {
+        Key::Char('\n') => "\\C-m".to_string(),
+        Key::Char('\t') => "\\C-i".to_string(),
+        Key::Char('\\') => "\\\\".to_string(),
+        Key::Char('"') => "\\\"".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Backspace => "\\C-?".to_string(),
+        Key::Esc => "\\e".to_string(),
+        Key::Ctrl(c @ '4'..='7') => format!("\\C-{}", (c as u8 - b'4' + b'\\') as char).replace("\\C-\\", "\\C-\\\\"),
+        Key::Ctrl(c) => format!("\\C-{}", c),
+        Key::Alt(c) => format!("\\e{}", format_key(Key::Char(c))),
+        key => match SPECIAL_KEYS.iter().find(|(_, k)| *k == key) {
+            Some((seq, _)) => format!("\\e{}", seq),
+            None => format!("{:?}", key),
+        },
+    }
+}
+
+//the byte offset of the character at `pos`, positions in the line being edited count characters
+fn byte_offset(s: &str, pos: usize) -> usize {
+    s.char_indices().nth(pos).map_or(s.len(), |(i, _)| i)
+}
+
+//replaces the characters from start to end with text, returning the ones replaced
+fn splice_chars(s: &mut String, start: usize, end: usize, text: &str) -> String {
+    let (start, end) = (byte_offset(s, start), byte_offset(s, end));
+    let removed = s[start..end].to_string();
+    s.replace_range(start..end, text);
+    removed
+}
+
+//where the word before pos starts, for backward-word words are letters and digits
+fn word_back(s: &str, pos: usize) -> usize {
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = pos;
+    while i > 0 && !chars[i - 1].is_alphanumeric() { i -= 1; }
+    while i > 0 && chars[i - 1].is_alphanumeric() { i -= 1; }
+    i
+}
+
+//where the word after pos ends
+fn word_forward(s: &str, pos: usize) -> usize {
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = pos;
+    while i < chars.len() && !chars[i].is_alphanumeric() { i += 1; }
+    while i < chars.len() && chars[i].is_alphanumeric() { i += 1; }
+    i
+}
+
+//unix-word-rubout's words are only separated by blanks
+fn blank_word_back(s: &str, pos: usize) -> usize {
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = pos;
+    while i > 0 && chars[i - 1].is_whitespace() { i -= 1; }
+    while i > 0 && !chars[i - 1].is_whitespace() { i -= 1; }
+    i
+}
+
+//redraws the line being edited and puts the cursor at `pos`, history entries can span
+//several lines so `row` keeps track of the one the cursor is on
+fn draw_input(out: &mut dyn Write, prompt: &str, input: &str, pos: usize, row: &mut usize) -> io::Result<()> {
+    if *row > 0 {
+        write!(out, "{}", termion::cursor::Up(*row as u16))?;
+    }
+    write!(out, "\r{}{}{}", termion::clear::AfterCursor, prompt, input.replace('\n', "\r\n"))?;
+    let head: String = input.chars().take(pos).collect();
+    *row = head.matches('\n').count();
+    let last_row = input.matches('\n').count();
+    if last_row > *row {
+        write!(out, "{}", termion::cursor::Up((last_row - *row) as u16))?;
+    }
+    write!(out, "\r")?;
+    let mut col = head.rsplit('\n').next().unwrap_or_default().chars().count();
+    if *row == 0 {
+        col += prompt.chars().count();
+    }
+    if col > 0 {
+        write!(out, "{}", termion::cursor::Right(col as u16))?;
+    }
+    Ok(())
+}
+
+//how reading a line at the prompt ended
+#[derive(Debug, PartialEq)]
+pub enum ReadResult {
+    Line(String),
+    //ctrl-c, the line is thrown away
+    Interrupted,
+    //ctrl-d on an empty line, or the keys ran out
+    Eof,
+    //vi's `v`, the line is to be edited in an external editor and run from there
+    Edit(String),
+}
+
+pub struct LineEditor {
+    pub keymap: HashMap<Key, Action>,
+    //completes the last word of a line, giving what it can become and the names to list
+    complete: fn(&str) -> (Vec<String>, Vec<String>),
+    kill_ring: Vec<String>,
+    vi: Vi,
+    vi_mode: bool,
+    line: String,
+    //the cursor, counted in characters
+    pos: usize,
+    //the line as typed, which going down past the last history entry comes back to
+    typed: String,
+    hist_pos: usize,
+    row: usize,
+    //the line as it was before each edit, typing a run of characters is one edit
+    undo: Vec<(String, usize)>,
+    //the last action, for the ones that carry on from it
+    last: Option<Action>,
+    //where the text of the last yank is, which yank-pop replaces
+    yanked: Option<(usize, usize)>,
+    yank_index: usize,
+}
+
+impl LineEditor {
+    pub fn new(complete: fn(&str) -> (Vec<String>, Vec<String>)) -> Self {
+        LineEditor {
+            keymap: default_keymap(),
+            complete,
+            kill_ring: Vec::new(),
+            vi: Vi::new(),
+            vi_mode: false,
+            line: String::new(),
+            pos: 0,
+            typed: String::new(),
+            hist_pos: 0,
+            row: 0,
+            undo: Vec::new(),
+            last: None,
+            yanked: None,
+            yank_index: 0,
+        }
+    }
+
+    //reads a line from keys, drawing it after the prompt that has already been written to out
+    pub fn read_line<W: Write>(&mut self, keys: impl Iterator<Item = Key>, out: &mut W, prompt: &str, history: &[String], vi_mode: bool) -> io::Result<ReadResult> {
+        self.line.clear();
+        self.pos = 0;
+        self.typed.clear();
+        self.hist_pos = history.len();
+        self.row = 0;
+        self.undo.clear();
+        self.last = None;
+        self.yanked = None;
+        self.vi_mode = vi_mode;
+        if vi_mode {
+            self.vi.start_line(&mut self.undo);
+            self.vi.show_mode(out)?;
+            out.flush()?;
+        }
+        let result = self.read_keys(keys, out, prompt, history);
+        if vi_mode {
+            write!(out, "{}", vi::DEFAULT_CURSOR)?;
+        }
+        out.flush()?;
+        result
+    }
+
+    fn read_keys<W: Write>(&mut self, keys: impl Iterator<Item = Key>, out: &mut W, prompt: &str, history: &[String]) -> io::Result<ReadResult> {
+        for mut key in keys {
+            if self.vi_mode {
+                match self.vi.key(key, &mut self.line, &mut self.pos, &mut self.undo) {
+                    ViResult::Pass(passed) => key = passed,
+                    //vi keeps its own undo steps, so the line only has to be redrawn
+                    ViResult::Handled => {
+                        self.typed = self.line.clone();
+                        self.redraw(out, prompt)?;
+                        continue;
+                    }
+                    ViResult::Editor => {
+                        write!(out, "\r\n")?;
+                        return Ok(ReadResult::Edit(self.line.clone()));
+                    }
+                }
+            }
+            //the terminal is in raw mode, so ctrl-c arrives as a key rather than a signal
+            if key == Key::Ctrl('c') {
+                write!(out, "^C\r\n")?;
+                return Ok(ReadResult::Interrupted);
+            }
+            let action = match (self.keymap.get(&key), key) {
+                (Some(action), _) => *action,
+                (None, Key::Char(_)) => Action::SelfInsert,
+                (None, _) => continue,
+            };
+            if let Some(result) = self.run_action(action, key, out, prompt, history)? {
+                return Ok(result);
+            }
+            self.redraw(out, prompt)?;
+        }
+        Ok(ReadResult::Eof)
+    }
+
+    fn redraw<W: Write>(&mut self, out: &mut W, prompt: &str) -> io::Result<()> {
+        if self.vi_mode {
+            self.vi.fix_cursor(&self.line, &mut self.pos);
+            self.vi.show_mode(out)?;
+        }
+        draw_input(out, prompt, &self.line, self.pos, &mut self.row)?;
+        out.flush()
+    }
+
+    //consecutive kills make one piece of text in the ring, in the order it was on the line
+    fn kill(&mut self, start: usize, end: usize, continued: bool) {
+        let text = splice_chars(&mut self.line, start, end, "");
+        match self.kill_ring.last_mut() {
+            Some(last) if continued && end <= self.pos => last.insert_str(0, &text),
+            Some(last) if continued => last.push_str(&text),
+            _ => {
+                self.kill_ring.push(text);
+                if self.kill_ring.len() > KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+            }
+        }
+        self.pos = start;
+    }
+
+    //shows another line in place of the one being edited, which isn't an edit that can be undone
+    fn replace_line(&mut self, line: String) {
+        self.line = line;
+        self.pos = self.line.chars().count();
+    }
+
+    fn run_action<W: Write>(&mut self, action: Action, key: Key, out: &mut W, prompt: &str, history: &[String]) -> io::Result<Option<ReadResult>> {
+        let last = self.last.replace(action);
+        let last_yank = self.yanked.take();
+        let killing = matches!(last, Some(Action::KillLine | Action::UnixLineDiscard | Action::UnixWordRubout));
+        let before = (self.line.clone(), self.pos);
+        let len = self.line.chars().count();
+        match action {
+            Action::AcceptLine => {
+                self.pos = len;
+                self.redraw(out, prompt)?;
+                write!(out, "\r\n")?;
+                return Ok(Some(ReadResult::Line(self.line.clone())));
+            }
+            Action::DeleteChar if self.line.is_empty() && key == Key::Ctrl('d') => return Ok(Some(ReadResult::Eof)),
+            Action::DeleteChar if self.pos < len => {
+                splice_chars(&mut self.line, self.pos, self.pos + 1, "");
+            }
+            Action::BackwardDeleteChar if self.pos > 0 => {
+                splice_chars(&mut self.line, self.pos - 1, self.pos, "");
+                self.pos -= 1;
+            }
+            Action::BackwardChar => self.pos = self.pos.saturating_sub(1),
+            Action::ForwardChar => self.pos = (self.pos + 1).min(len),
+            Action::BeginningOfLine => self.pos = 0,
+            Action::EndOfLine => self.pos = len,
+            Action::BackwardWord => self.pos = word_back(&self.line, self.pos),
+            Action::ForwardWord => self.pos = word_forward(&self.line, self.pos),
+            Action::KillLine => self.kill(self.pos, len, killing),
+            Action::UnixLineDiscard => self.kill(0, self.pos, killing),
+            Action::UnixWordRubout => self.kill(blank_word_back(&self.line, self.pos), self.pos, killing),
+            Action::Yank if !self.kill_ring.is_empty() => {
+                self.yank_index = self.kill_ring.len() - 1;
+                let text = &self.kill_ring[self.yank_index];
+                splice_chars(&mut self.line, self.pos, self.pos, text);
+                self.yanked = Some((self.pos, text.chars().count()));
+                self.pos += text.chars().count();
+            }
+            //right after a yank, swaps what was yanked for the text killed before it
+            Action::YankPop if last_yank.is_some() => {
+                let (start, len) = last_yank.unwrap();
+                self.yank_index = (self.yank_index + self.kill_ring.len() - 1) % self.kill_ring.len();
+                let text = &self.kill_ring[self.yank_index];
+                splice_chars(&mut self.line, start, start + len, text);
+                self.yanked = Some((start, text.chars().count()));
+                self.pos = start + text.chars().count();
+            }
+            //swaps the characters around the cursor, or the last two at the end of the line
+            Action::TransposeChars if self.pos > 0 && len >= 2 => {
+                let pos = self.pos.min(len - 1);
+                let pair: Vec<char> = self.line.chars().skip(pos - 1).take(2).collect();
+                splice_chars(&mut self.line, pos - 1, pos + 1, &format!("{}{}", pair[1], pair[0]));
+                self.pos = pos + 1;
+            }
+            Action::ClearScreen => {
+                write!(out, "{}{}", termion::clear::All, termion::cursor::Goto(1, 1))?;
+                self.row = 0;
+            }
+            Action::Undo => {
+                if let Some((line, pos)) = self.undo.pop() {
+                    self.line = line;
+                    self.pos = pos;
+                    self.typed = self.line.clone();
+                }
+                return Ok(None);
+            }
+            Action::PreviousHistory if self.hist_pos > 0 => {
+                self.hist_pos -= 1;
+                self.replace_line(history[self.hist_pos].clone());
+                return Ok(None);
+            }
+            Action::NextHistory => {
+                self.hist_pos = (self.hist_pos + 1).min(history.len());
+                let line = history.get(self.hist_pos).unwrap_or(&self.typed).clone();
+                self.replace_line(line);
+                return Ok(None);
+            }
+            Action::Complete => {
+                let results = (self.complete)(&self.line);
+                if results.0.len() == 1 {
+                    let mut argv: Vec<&str> = self.line.split_whitespace().collect();
+                    let len = argv.len();
+                    argv[len - 1] = &results.0[0];
+                    let line = argv.join(" ");
+                    self.line = line;
+                    self.pos = self.line.chars().count();
+                } else if results.0.len() > 1 {
+                    write!(out, "\r\n")?;
+                    for n in results.1 {
+                        write!(out, "{} ", n)?;
+                    }
+                    write!(out, "\r\n")?;
+                    self.row = 0;
+                }
+            }
+            Action::SelfInsert => {
+                if let Key::Char(c) = key {
+                    splice_chars(&mut self.line, self.pos, self.pos, &c.to_string());
+                    self.pos += 1;
+                }
+            }
+            _ => (),
+        }
+        if self.line != before.0 {
+            if !(action == Action::SelfInsert && last == Some(Action::SelfInsert)) {
+                self.undo.push(before);
+            }
+            self.typed = self.line.clone();
+        }
+        Ok(None)
+    }
+}
+
+impl Shell {
+    fn bindings(&self) -> String {
+        let mut bindings: Vec<(&str, String)> = self.editor.keymap.iter()
+            .map(|(key, action)| (action_name(*action), format_key(*key)))
+            .collect();
+        bindings.sort();
+        bindings.iter().map(|(name, key)| format!("\"{}\": {}\n", key, name)).collect()
+    }
+
+    //`bind [-lp] [-r keyseq] ['keyseq: action' ...]`, keys are written as readline writes them
+    pub fn bind_builtin(&mut self, argv: &[String]) -> i32 {
+        let mut args = argv.iter();
+        let mut status = 0;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-l" => {
+                    let mut names: Vec<&str> = ACTIONS.iter().map(|(name, _)| *name).collect();
+                    names.sort();
+                    let text: String = names.iter().map(|name| format!("{}\n", name)).collect();
+                    if write_out("bind", &text) != 0 {
+                        return 1;
+                    }
+                }
+                "-p" => {
+                    if write_out("bind", &self.bindings()) != 0 {
+                        return 1;
+                    }
+                }
+                "-r" => {
+                    let seq = match args.next() {
+                        Some(seq) => seq.trim_matches('"'),
+                        None => {
+                            eprintln!("josh: bind: -r: option requires an argument");
+                            return 2;
+                        }
+                    };
+                    match parse_key(seq) {
+                        Some(key) => {
+                            self.editor.keymap.remove(&key);
+                        }
+                        None => {
+                            eprintln!("josh: bind: {}: invalid key sequence", seq);
+                            status = 1;
+                        }
+                    }
+                }
+                arg if arg.starts_with('-') => {
+                    eprintln!("josh: bind: {}: invalid option", arg);
+                    eprintln!("bind: usage: bind [-lp] [-r keyseq] [keyseq:function-name]");
+                    return 2;
+                }
+                arg => {
+                    //a quoted key sequence can hold a colon of its own
+                    let split = match arg.strip_prefix('"') {
+                        Some(rest) => rest.find('"').and_then(|end| Some((&rest[..end], rest[end + 1..].trim_start().strip_prefix(':')?))),
+                        None => arg.split_once(':'),
+                    };
+                    let (seq, name) = match split {
+                        Some((seq, name)) => (seq, name.trim()),
+                        None => {
+                            eprintln!("josh: bind: `{}': missing colon separator", arg);
+                            status = 1;
+                            continue;
+                        }
+                    };
+                    let action = match ACTIONS.iter().find(|(n, _)| *n == name) {
+                        Some((_, action)) => *action,
+                        None => {
+                            eprintln!("josh: bind: `{}': unknown function name", name);
+                            status = 1;
+                            continue;
+                        }
+                    };
+                    match parse_key(seq) {
+                        Some(key) => {
+                            self.editor.keymap.insert(key, action);
+                        }
+                        None => {
+                            eprintln!("josh: bind: {}: invalid key sequence", seq);
+                            status = 1;
+                        }
+                    }
+                }
+            }
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_completions(_: &str) -> (Vec<String>, Vec<String>) {
+        (Vec::new(), Vec::new())
+    }
+
+    fn typed(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    //reads a line from keys, an enter is added at the end
+    fn read(editor: &mut LineEditor, mut keys: Vec<Key>, history: &[String]) -> ReadResult {
+        keys.push(Key::Char('\n'));
+        let mut out = Vec::new();
+        editor.read_line(keys.into_iter(), &mut out, "> ", history, false).unwrap()
+    }
+
+    fn line(editor: &mut LineEditor, keys: Vec<Key>) -> String {
+        match read(editor, keys, &[]) {
+            ReadResult::Line(line) => line,
+            result => panic!("expected a line, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn kill_and_yank() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut keys = typed("foo bar");
+        keys.extend([Key::Ctrl('w'), Key::Ctrl('a'), Key::Ctrl('k'), Key::Ctrl('y')]);
+        assert_eq!(line(&mut editor, keys.clone()), "foo ");
+        keys.push(Key::Alt('y'));
+        assert_eq!(line(&mut editor, keys.clone()), "bar");
+        //the ring goes round
+        keys.push(Key::Alt('y'));
+        assert_eq!(line(&mut editor, keys), "foo ");
+    }
+
+    #[test]
+    fn consecutive_kills_join() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut keys = typed("one two three");
+        keys.extend([Key::Ctrl('w'), Key::Ctrl('w'), Key::Ctrl('y')]);
+        assert_eq!(line(&mut editor, keys), "one two three");
+        //the kill ring is kept from line to line
+        assert_eq!(line(&mut editor, vec![Key::Ctrl('y')]), "two three");
+        //alt-y only works right after a yank
+        assert_eq!(line(&mut editor, vec![Key::Alt('y')]), "");
+    }
+
+    #[test]
+    fn multibyte_text() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut keys = typed("héllo wörld");
+        keys.push(Key::Ctrl('w'));
+        assert_eq!(line(&mut editor, keys), "héllo ");
+        let mut keys = typed("añb");
+        keys.push(Key::Ctrl('t'));
+        assert_eq!(line(&mut editor, keys), "abñ");
+        let mut keys = typed("äöü");
+        keys.extend([Key::Left, Key::Left, Key::Ctrl('t'), Key::Char('x')]);
+        assert_eq!(line(&mut editor, keys), "öäxü");
+    }
+
+    #[test]
+    fn undo_groups_typing() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut keys = typed("abc");
+        keys.push(Key::Ctrl('7'));
+        assert_eq!(line(&mut editor, keys), "");
+        let mut keys = typed("abc");
+        keys.extend([Key::Backspace, Key::Ctrl('7')]);
+        assert_eq!(line(&mut editor, keys.clone()), "abc");
+        keys.push(Key::Ctrl('7'));
+        assert_eq!(line(&mut editor, keys), "");
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = LineEditor::new(no_completions);
+        let history = vec!["first".to_string(), "second".to_string()];
+        let mut keys = typed("new");
+        keys.push(Key::Up);
+        assert_eq!(read(&mut editor, keys.clone(), &history), ReadResult::Line("second".to_string()));
+        keys.extend([Key::Up, Key::Up]);
+        assert_eq!(read(&mut editor, keys.clone(), &history), ReadResult::Line("first".to_string()));
+        keys.extend([Key::Down, Key::Down]);
+        assert_eq!(read(&mut editor, keys, &history), ReadResult::Line("new".to_string()));
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut out = Vec::new();
+        let keys = vec![Key::Ctrl('d')];
+        assert_eq!(editor.read_line(keys.into_iter(), &mut out, "> ", &[], false).unwrap(), ReadResult::Eof);
+        let keys = vec![Key::Char('x'), Key::Ctrl('c')];
+        assert_eq!(editor.read_line(keys.into_iter(), &mut out, "> ", &[], false).unwrap(), ReadResult::Interrupted);
+        //ctrl-d deletes when there is something on the line
+        let keys = vec![Key::Char('x'), Key::Ctrl('a'), Key::Ctrl('d')];
+        assert_eq!(read(&mut editor, keys, &[]), ReadResult::Line(String::new()));
+    }
+
+    #[test]
+    fn bind() {
+        let mut shell = Shell::new();
+        let mut keys = typed("ac");
+        keys.extend([Key::Ctrl('a'), Key::Ctrl('f'), Key::Char('b')]);
+        assert_eq!(line(&mut shell.editor, keys.clone()), "bac");
+        assert_eq!(shell.bind_builtin(&[r#""\C-f": forward-char"#.to_string()]), 0);
+        assert_eq!(line(&mut shell.editor, keys.clone()), "abc");
+        assert_eq!(shell.bind_builtin(&["-r".to_string(), r"\C-f".to_string()]), 0);
+        assert_eq!(line(&mut shell.editor, keys), "bac");
+        assert_eq!(shell.bind_builtin(&[r#""\C-f": no-such-action"#.to_string()]), 1);
+        assert_eq!(shell.bind_builtin(&["forward-char".to_string()]), 1);
+    }
+
+    //the text typed before the first escape counts as an insert, for `.` and `u`
+    #[test]
+    fn vi_first_insert() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut out = Vec::new();
+        let mut keys = typed("xyz");
+        keys.extend([Key::Esc]);
+        keys.extend(typed("0.\n"));
+        let result = editor.read_line(keys.clone().into_iter(), &mut out, "> ", &[], true).unwrap();
+        assert_eq!(result, ReadResult::Line("xyzxyz".to_string()));
+        keys.insert(keys.len() - 1, Key::Char('u'));
+        let result = editor.read_line(keys.into_iter(), &mut out, "> ", &[], true).unwrap();
+        assert_eq!(result, ReadResult::Line("xyz".to_string()));
+        let mut keys = typed("echo xyz");
+        keys.extend([Key::Esc]);
+        keys.extend(typed("u\n"));
+        let result = editor.read_line(keys.into_iter(), &mut out, "> ", &[], true).unwrap();
+        assert_eq!(result, ReadResult::Line(String::new()));
+    }
+
+    #[test]
+    fn vi_long_counts() {
+        let mut editor = LineEditor::new(no_completions);
+        let mut out = Vec::new();
+        let mut keys = typed("abc");
+        keys.extend([Key::Esc, Key::Char('0')]);
+        keys.extend(typed(&"9".repeat(20)));
+        keys.extend(typed("x\n"));
+        let result = editor.read_line(keys.into_iter(), &mut out, "> ", &[], true).unwrap();
+        assert_eq!(result, ReadResult::Line(String::new()));
+        let mut keys = typed("a");
+        keys.extend([Key::Esc]);
+        keys.extend(typed(&format!("yl{}p\n", "9".repeat(20))));
+        let result = editor.read_line(keys.into_iter(), &mut out, "> ", &[], true).unwrap();
+        assert_eq!(result, ReadResult::Line("a".repeat(10000)));
+    }
+
+    #[test]
+    fn key_notation() {
+        let keys = [
+            Key::Ctrl('a'), Key::Ctrl('7'), Key::Ctrl('4'), Key::Alt('f'), Key::Alt('"'),
+            Key::Up, Key::Down, Key::Left, Key::Right, Key::Home, Key::End, Key::Delete,
+            Key::Backspace, Key::Esc, Key::Char('\n'), Key::Char('\t'), Key::Char('x'),
+            Key::Char('"'), Key::Char('\\'),
+        ];
+        for key in keys {
+            assert_eq!(parse_key(&format_key(key)), Some(key), "{}", format_key(key));
+        }
+        assert_eq!(parse_key(r"\M-f"), Some(Key::Alt('f')));
+        assert_eq!(parse_key(r"\C-M"), Some(Key::Char('\n')));
+        assert_eq!(parse_key(r"\C-_"), Some(Key::Ctrl('7')));
+        assert_eq!(parse_key(r"\e[A"), Some(Key::Up));
+        assert_eq!(parse_key("ab"), None);
+        assert_eq!(parse_key(r"\C-"), None);
+        assert_eq!(parse_key(r"\C-ab"), None);
+    }
+}
@@ -0,0 +1,680 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, ErrorKind, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::rc::Rc;
+
+use crate::array::Value;
+use crate::jobs::Job;
+use crate::lexer::{ParseError, Word};
+use crate::glob;
+use crate::parser::{self, AndOr, CaseEnd, CaseItem, Command, CompoundCommand, Connector, List, Pipeline, Redirect, RedirectOp, SimpleCommand};
+use crate::sys::{self, Fork};
+use crate::{Shell, Var};
+
+//set by `break`, `continue` and `return`, unwound by the enclosing loops and functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Normal,
+    Break(u32),
+    Continue(u32),
+    Return,
+}
+
+//fds replaced by redirections, with copies of what they were before (None if closed)
+type SavedFds = Vec<(RawFd, Option<RawFd>)>;
+
+fn save_fd(saved: &mut SavedFds, fd: RawFd) {
+    if !saved.iter().any(|(f, _)| *f == fd) {
+        saved.push((fd, sys::dup_saved(fd).ok()));
+    }
+}
+
+//moves an opened file onto fd
+fn install_fd(saved: &mut SavedFds, file: RawFd, fd: RawFd) {
+    save_fd(saved, fd);
+    if file != fd {
+        sys::dup2(file, fd).ok();
+        sys::close(file);
+    }
+}
+
+fn open_target(target: &str, options: &mut OpenOptions) -> Result<RawFd, String> {
+    options.open(target)
+        .map(|file| file.into_raw_fd())
+        .map_err(|e| format!("{}: {}", target, sys::error_message(&e)))
+}
+
+//the text of a here-document or here-string as a file to read, which is unlinked right away
+fn here_document(text: &str) -> Result<RawFd, String> {
+    let error = |e: std::io::Error| format!("cannot create temp file for here-document: {}", sys::error_message(&e));
+    let mut n = 0;
+    let (path, mut file) = loop {
+        let path = std::env::temp_dir().join(format!("josh-here-{}-{}", sys::getpid(), n));
+        match OpenOptions::new().read(true).write(true).create_new(true).mode(0o600).open(&path) {
+            Ok(file) => break (path, file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(error(e)),
+        }
+    };
+    std::fs::remove_file(&path).ok();
+    file.write_all(text.as_bytes()).map_err(error)?;
+    file.seek(SeekFrom::Start(0)).map_err(error)?;
+    Ok(file.into_raw_fd())
+}
+
+impl Shell {
+    //adds a line to the source read so far, returning the commands once it forms complete ones
+    //and None while more lines are needed
+    pub fn parse_line(&mut self, pending: &mut String, line: &str) -> Result<Option<List>, ParseError> {
+        pending.push_str(line);
+        pending.push('\n');
+        match parser::parse(pending, &self.aliases) {
+            Err(ParseError::Incomplete(_)) => Ok(None),
+            Ok(list) => {
+                pending.clear();
+                Ok(Some(list))
+            }
+            Err(e) => {
+                pending.clear();
+                self.last_status = 2;
+                Err(e)
+            }
+        }
+    }
+
+    //like parse_line but runs the commands, returns true while more lines are needed
+    pub fn feed_line(&mut self, pending: &mut String, line: &str) -> Result<bool, ParseError> {
+        match self.parse_line(pending, line)? {
+            Some(list) => {
+                self.run_list(&list);
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    //runs a script, rc file or -c string, stopping at the first syntax error
+    pub fn run_lines<R: BufRead>(&mut self, reader: R, name: &str) {
+        let mut pending = String::new();
+        let mut line_num = 0;
+        for line in reader.lines() {
+            line_num += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("{}: {}", name, sys::error_message(&e));
+                    self.last_status = 1;
+                    return;
+                }
+            };
+            if let Err(e) = self.feed_line(&mut pending, &line) {
+                eprintln!("{}: line {}: {}", name, line_num, e);
+                return;
+            }
+            if self.exiting {
+                return;
+            }
+        }
+        if !pending.is_empty() {
+            eprintln!("{}: line {}: syntax error: unexpected end of file", name, line_num);
+            self.last_status = 2;
+        }
+    }
+
+    //whether the rest of the current list should be skipped
+    fn unwinding(&self) -> bool {
+        self.exiting || self.interrupted || self.flow != Flow::Normal
+    }
+
+    pub fn run_list(&mut self, list: &List) {
+        for and_or in &list.items {
+            self.run_and_or(and_or);
+            if self.unwinding() {
+                break;
+            }
+        }
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr) {
+        if and_or.background {
+            self.run_background(and_or);
+            return;
+        }
+        self.last_status = self.run_pipeline(&and_or.first, &and_or.text);
+        self.check_signals();
+        //only a failure of the last pipeline counts for ERR, the others are being tested
+        let mut last = &and_or.first;
+        for (connector, pipeline) in &and_or.rest {
+            if self.unwinding() {
+                return;
+            }
+            let run = match connector {
+                Connector::And => self.last_status == 0,
+                Connector::Or => self.last_status != 0,
+            };
+            if run {
+                self.last_status = self.run_pipeline(pipeline, &and_or.text);
+                self.check_signals();
+                last = pipeline;
+            }
+        }
+        let tested = !std::ptr::eq(last, and_or.rest.last().map(|(_, p)| p).unwrap_or(&and_or.first));
+        if self.last_status != 0 && !last.negated && !tested {
+            self.run_err_trap();
+        }
+    }
+
+    //`cmd &` runs in a forked shell that is not waited for
+    fn run_background(&mut self, and_or: &AndOr) {
+        match sys::fork() {
+            Ok(Fork::Child) => {
+                //a job gets its own process group even without job control, for `kill %n`
+                sys::setpgid(0, 0);
+                //without job control a background job can't be kept from reading the terminal
+                let stdin = if self.job_control { None } else { OpenOptions::new().read(true).open("/dev/null").ok() };
+                if let Some(file) = stdin {
+                    let fd = file.into_raw_fd();
+                    sys::dup2(fd, 0).ok();
+                    sys::close(fd);
+                }
+                self.enter_child();
+                let foreground = AndOr { background: false, ..and_or.clone() };
+                self.run_and_or(&foreground);
+                sys::exit(self.last_status);
+            }
+            Ok(Fork::Parent(pid)) => {
+                sys::setpgid(pid, pid);
+                let mut job = Job::new(pid, vec![pid], &and_or.text);
+                job.notified = true;
+                let id = self.add_job(job);
+                if self.job_control {
+                    eprintln!("[{}] {}", id, pid);
+                }
+                self.last_bg_pid = Some(pid);
+                self.last_status = 0;
+            }
+            Err(e) => {
+                eprintln!("josh: fork: {}", e);
+                self.last_status = 1;
+            }
+        }
+    }
+
+    //a forked shell leaves job control to the shell that forked it
+    pub fn enter_child(&mut self) {
+        self.job_control = false;
+        self.reset_traps();
+        self.jobs.clear();
+    }
+
+    //runs a list in a forked shell, so that `cd`, variables and traps don't outlive it
+    fn run_subshell(&mut self, body: &List, text: &str) -> i32 {
+        match sys::fork() {
+            Ok(Fork::Child) => {
+                if self.job_control {
+                    sys::setpgid(0, 0);
+                }
+                self.enter_child();
+                self.run_list(body);
+                self.run_exit_trap();
+                sys::exit(self.last_status);
+            }
+            Ok(Fork::Parent(pid)) => {
+                if self.job_control {
+                    sys::setpgid(pid, pid);
+                }
+                self.wait_foreground(Job::new(pid, vec![pid], text))
+            }
+            Err(e) => {
+                eprintln!("josh: fork: {}", e);
+                1
+            }
+        }
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline, text: &str) -> i32 {
+        let status = self.run_pipeline_commands(pipeline, text);
+        if pipeline.negated {
+            (status == 0) as i32
+        } else {
+            status
+        }
+    }
+
+    fn run_pipeline_commands(&mut self, pipeline: &Pipeline, text: &str) -> i32 {
+        if let [command] = pipeline.commands.as_slice() {
+            return self.run_command(command);
+        }
+
+        //every stage runs in its own fork, reading from the previous stage's pipe
+        let mut pids: Vec<libc::pid_t> = Vec::new();
+        let mut stdin_fd: Option<RawFd> = None;
+        let last = pipeline.commands.len() - 1;
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            let pipe = if i < last {
+                match sys::pipe() {
+                    Ok(pipe) => Some(pipe),
+                    Err(e) => {
+                        eprintln!("josh: pipe: {}", e);
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+
+            //the whole pipeline shares the process group of its first command
+            let pgid = pids.first().copied().unwrap_or(0);
+            match sys::fork() {
+                Ok(Fork::Child) => {
+                    if self.job_control {
+                        sys::setpgid(0, pgid);
+                    }
+                    self.enter_child();
+                    if let Some(fd) = stdin_fd {
+                        sys::dup2(fd, 0).ok();
+                        sys::close(fd);
+                    }
+                    if let Some((read, write)) = pipe {
+                        sys::dup2(write, 1).ok();
+                        sys::close(write);
+                        sys::close(read);
+                    }
+                    let status = self.run_command(command);
+                    sys::exit(status);
+                }
+                Ok(Fork::Parent(pid)) => {
+                    if self.job_control {
+                        sys::setpgid(pid, if pgid == 0 { pid } else { pgid });
+                    }
+                    pids.push(pid);
+                }
+                Err(e) => eprintln!("josh: fork: {}", e),
+            }
+
+            if let Some(fd) = stdin_fd {
+                sys::close(fd);
+            }
+            stdin_fd = pipe.map(|(read, write)| {
+                sys::close(write);
+                read
+            });
+        }
+        if let Some(fd) = stdin_fd {
+            sys::close(fd);
+        }
+
+        match pids.first() {
+            Some(pgid) => self.wait_foreground(Job::new(*pgid, pids.clone(), text)),
+            None => 1,
+        }
+    }
+
+    fn run_command(&mut self, command: &Command) -> i32 {
+        let mark = self.proc_subs.len();
+        let status = match command {
+            Command::Simple(simple) => self.run_simple(simple),
+            Command::Compound(compound, redirects) => match self.apply_redirects(redirects) {
+                Ok(saved) => {
+                    let status = self.run_compound(compound);
+                    self.restore_fds(saved);
+                    status
+                }
+                Err(()) => 1,
+            },
+            Command::FunctionDef { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                0
+            }
+        };
+        self.end_process_subs(mark);
+        status
+    }
+
+    pub fn call_function(&mut self, body: Rc<Command>, argv: &[String]) -> i32 {
+        let positional = std::mem::replace(&mut self.positional, argv.to_vec());
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        self.locals.push(Vec::new());
+        let status = self.run_command(&body);
+        //locals are dynamically scoped, the values they shadowed come back on return
+        for (name, old) in self.locals.pop().unwrap().into_iter().rev() {
+            match old {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+        self.positional = positional;
+        self.loop_depth = loop_depth;
+        if self.flow == Flow::Return {
+            self.flow = Flow::Normal;
+        }
+        status
+    }
+
+    //handles break and continue at the end of a loop iteration, returns true if the loop should stop
+    fn end_of_iteration(&mut self) -> bool {
+        match self.flow {
+            Flow::Break(n) => {
+                self.flow = if n > 1 { Flow::Break(n - 1) } else { Flow::Normal };
+                true
+            }
+            Flow::Continue(n) if n > 1 => {
+                self.flow = Flow::Continue(n - 1);
+                true
+            }
+            Flow::Continue(_) => {
+                self.flow = Flow::Normal;
+                false
+            }
+            Flow::Return => true,
+            Flow::Normal => self.unwinding(),
+        }
+    }
+
+    //evaluates one clause of an arithmetic for loop
+    pub fn eval_arith_word(&mut self, word: &Word) -> Result<i64, String> {
+        let text = self.expand_word_to_string(word);
+        self.eval_arith(&text).map_err(|e| format!("{}: {}", text.trim(), e))
+    }
+
+    fn run_compound(&mut self, compound: &CompoundCommand) -> i32 {
+        match compound {
+            CompoundCommand::If { branches, else_branch } => {
+                for (cond, body) in branches {
+                    self.condition_depth += 1;
+                    self.run_list(cond);
+                    self.condition_depth -= 1;
+                    if self.unwinding() {
+                        return self.last_status;
+                    }
+                    if self.last_status == 0 {
+                        self.run_list(body);
+                        return self.last_status;
+                    }
+                }
+                match else_branch {
+                    Some(body) => {
+                        self.run_list(body);
+                        self.last_status
+                    }
+                    None => 0,
+                }
+            }
+            CompoundCommand::Loop { until, cond, body } => {
+                let mut status = 0;
+                self.loop_depth += 1;
+                loop {
+                    self.condition_depth += 1;
+                    self.run_list(cond);
+                    self.condition_depth -= 1;
+                    if self.unwinding() {
+                        self.end_of_iteration();
+                        break;
+                    }
+                    if (self.last_status == 0) == *until {
+                        break;
+                    }
+                    self.run_list(body);
+                    status = self.last_status;
+                    if self.end_of_iteration() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::For { name, words, body } => {
+                let items = match words {
+                    Some(words) => self.expand_words(words),
+                    None => self.positional.clone(),
+                };
+                if std::mem::take(&mut self.expand_failed) {
+                    return 1;
+                }
+                let mut status = 0;
+                self.loop_depth += 1;
+                for item in items {
+                    self.set_var(name, item);
+                    self.run_list(body);
+                    status = self.last_status;
+                    if self.end_of_iteration() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::Group(list) => {
+                self.run_list(list);
+                self.last_status
+            }
+            CompoundCommand::Subshell { body, text } => self.run_subshell(body, text),
+            //true when the result is not zero
+            CompoundCommand::Arith(word) => match self.eval_arith_word(word) {
+                Ok(n) => (n == 0) as i32,
+                Err(e) => {
+                    eprintln!("josh: {}", e);
+                    1
+                }
+            },
+            CompoundCommand::Case { word, items } => {
+                let value = self.expand_word_to_string(word);
+                let mut status = 0;
+                let mut fall_through = false;
+                for item in items {
+                    if !fall_through && !self.case_matches(item, &value) {
+                        continue;
+                    }
+                    self.run_list(&item.body);
+                    status = self.last_status;
+                    if self.unwinding() {
+                        break;
+                    }
+                    match item.end {
+                        CaseEnd::Break => break,
+                        CaseEnd::FallThrough => fall_through = true,
+                        CaseEnd::Continue => fall_through = false,
+                    }
+                }
+                status
+            }
+            CompoundCommand::Cond(expr) => match self.eval_cond(expr) {
+                Ok(res) => !res as i32,
+                Err(e) => {
+                    eprintln!("josh: {}", e);
+                    2
+                }
+            },
+            CompoundCommand::ArithFor { init, cond, step, body } => {
+                self.loop_depth += 1;
+                let res = self.run_arith_for(init, cond, step, body);
+                self.loop_depth -= 1;
+                match res {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("josh: {}", e);
+                        1
+                    }
+                }
+            }
+        }
+    }
+
+    fn case_matches(&mut self, item: &CaseItem, value: &str) -> bool {
+        for pattern in &item.patterns {
+            let pattern = self.expand_pattern(pattern);
+            if glob::matches(&pattern, value, false) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn run_arith_for(&mut self, init: &Word, cond: &Word, step: &Word, body: &List) -> Result<i32, String> {
+        let mut status = 0;
+        self.eval_arith_word(init)?;
+        loop {
+            //an empty condition counts as true
+            let text = self.expand_word_to_string(cond);
+            if !text.trim().is_empty() && self.eval_arith_word(cond)? == 0 {
+                break;
+            }
+            self.run_list(body);
+            status = self.last_status;
+            if self.end_of_iteration() {
+                break;
+            }
+            self.eval_arith_word(step)?;
+        }
+        Ok(status)
+    }
+
+    fn run_simple(&mut self, simple: &SimpleCommand) -> i32 {
+        self.run_debug_trap();
+        self.sub_status = None;
+        let argv = self.expand_words(&simple.words);
+        if std::mem::take(&mut self.expand_failed) {
+            return 1;
+        }
+        //`VAR=value cmd` only exports VAR to cmd, the old values are put back afterwards
+        let mut saved_vars: Vec<(String, Option<Var>)> = Vec::new();
+        for assign in &simple.assigns {
+            if argv.is_empty() || assign.index.is_some() || assign.array.is_some() {
+                self.assign(assign);
+            } else {
+                let mut value = self.expand_word_to_string(&assign.value);
+                if assign.append {
+                    let old = self.vars.get(&assign.name).and_then(|var| var.value.scalar()).unwrap_or_default();
+                    value.insert_str(0, old);
+                }
+                let old = self.vars.insert(assign.name.clone(), Var { value: Value::Scalar(value), exported: true });
+                saved_vars.push((assign.name.clone(), old));
+            }
+        }
+        //an assignment's expansion can fail too
+        let status = if std::mem::take(&mut self.expand_failed) {
+            1
+        } else {
+            match self.apply_redirects(&simple.redirects) {
+                Ok(saved) => {
+                    //with no command the status is that of the last command substitution
+                    let status = if argv.is_empty() {
+                        self.sub_status.unwrap_or(0)
+                    } else {
+                        self.execute_command(&argv[0], &argv[1..])
+                    };
+                    if status == 0 {
+                        for assign in &simple.declared {
+                            self.assign(assign);
+                        }
+                    }
+                    self.restore_fds(saved);
+                    status
+                }
+                Err(()) => 1,
+            }
+        };
+        for (name, old) in saved_vars.into_iter().rev() {
+            match old {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+        status
+    }
+
+    //redirections are done on the shell's own fds so that builtins see them too
+    //and spawned commands inherit them, restore_fds undoes them afterwards
+    fn apply_redirects(&mut self, redirects: &[Redirect]) -> Result<SavedFds, ()> {
+        std::io::stdout().flush().ok();
+        let mut saved = SavedFds::new();
+        for redirect in redirects {
+            if let Err(msg) = self.apply_redirect(redirect, &mut saved) {
+                eprintln!("josh: {}", msg);
+                self.restore_fds(saved);
+                return Err(());
+            }
+        }
+        Ok(saved)
+    }
+
+    fn apply_redirect(&mut self, redirect: &Redirect, saved: &mut SavedFds) -> Result<(), String> {
+        //here-documents aren't split, any other target has to stay a single word
+        let target = match redirect.op {
+            RedirectOp::HereDoc | RedirectOp::HereString => self.expand_word_to_string(&redirect.target),
+            _ => match self.expand_word(&redirect.target).as_slice() {
+                [target] => target.clone(),
+                _ => return Err("ambiguous redirect".to_string()),
+            },
+        };
+        match redirect.op {
+            RedirectOp::HereDoc => {
+                let file = here_document(&target)?;
+                install_fd(saved, file, redirect.fd.unwrap_or(0));
+            }
+            RedirectOp::HereString => {
+                let file = here_document(&format!("{}\n", target))?;
+                install_fd(saved, file, redirect.fd.unwrap_or(0));
+            }
+            RedirectOp::In => {
+                let file = open_target(&target, OpenOptions::new().read(true))?;
+                install_fd(saved, file, redirect.fd.unwrap_or(0));
+            }
+            RedirectOp::Out => {
+                let file = open_target(&target, OpenOptions::new().write(true).create(true).truncate(true))?;
+                install_fd(saved, file, redirect.fd.unwrap_or(1));
+            }
+            RedirectOp::Append => {
+                let file = open_target(&target, OpenOptions::new().append(true).create(true))?;
+                install_fd(saved, file, redirect.fd.unwrap_or(1));
+            }
+            RedirectOp::OutErr | RedirectOp::AppendErr => {
+                let mut options = OpenOptions::new();
+                if redirect.op == RedirectOp::OutErr {
+                    options.write(true).create(true).truncate(true);
+                } else {
+                    options.append(true).create(true);
+                }
+                let file = open_target(&target, &mut options)?;
+                save_fd(saved, 2);
+                sys::dup2(file, 2).ok();
+                install_fd(saved, file, 1);
+            }
+            RedirectOp::DupIn | RedirectOp::DupOut => {
+                let fd = redirect.fd.unwrap_or(if redirect.op == RedirectOp::DupIn { 0 } else { 1 });
+                if target == "-" {
+                    save_fd(saved, fd);
+                    sys::close(fd);
+                } else if let Ok(from) = target.parse::<RawFd>() {
+                    if from != fd {
+                        save_fd(saved, fd);
+                        sys::dup2(from, fd).map_err(|_| format!("{}: Bad file descriptor", from))?;
+                    }
+                } else if redirect.op == RedirectOp::DupOut && redirect.fd.is_none() {
+                    //`>&file` is the same as `&>file`
+                    let file = open_target(&target, OpenOptions::new().write(true).create(true).truncate(true))?;
+                    save_fd(saved, 2);
+                    sys::dup2(file, 2).ok();
+                    install_fd(saved, file, 1);
+                } else {
+                    return Err(format!("{}: ambiguous redirect", target));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_fds(&mut self, saved: SavedFds) {
+        std::io::stdout().flush().ok();
+        for (fd, copy) in saved.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    sys::dup2(copy, fd).ok();
+                    sys::close(copy);
+                }
+                None => sys::close(fd),
+            }
+        }
+    }
+}
@@ -0,0 +1,647 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use crate::glob;
+use crate::lexer::{ParamOp, ReplaceMode, Word, WordPart};
+use crate::parser::{self, List};
+use crate::sys::{self, Fork};
+use crate::{expand_tilde, Shell};
+
+//what a `${...}` expansion turns into
+enum ParamValue<'a> {
+    Value(String),
+    //from `${@...}`, `${*...}` and `${name[@]...}`, which keep their items apart like `$@`
+    List(Vec<String>),
+    //the operand of `-` or `+`, expanded in place of the parameter
+    Word(&'a Word),
+}
+
+//`$@` and `${name[@]}` keep their items apart even in double quotes, `$*` and `${name[*]}` join them
+fn keeps_items_apart(name: &str) -> bool {
+    name == "@" || name.ends_with("[@]")
+}
+
+//the byte offsets of every character boundary in s, including both ends
+fn boundaries(s: &str) -> Vec<usize> {
+    s.char_indices().map(|(i, _)| i).chain(std::iter::once(s.len())).collect()
+}
+
+fn remove_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|end| glob::matches(pattern, &value[..*end], false)) {
+        Some(end) => value[end..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn remove_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts.into_iter().find(|start| glob::matches(pattern, &value[*start..], false)) {
+        Some(start) => value[..start].to_string(),
+        None => value.to_string(),
+    }
+}
+
+//replaces the longest matches of pattern, scanning from the left
+fn replace(value: &str, pattern: &str, replacement: &str, mode: ReplaceMode) -> String {
+    if pattern.is_empty() {
+        return match mode {
+            ReplaceMode::Prefix => format!("{}{}", replacement, value),
+            ReplaceMode::Suffix => format!("{}{}", value, replacement),
+            _ => value.to_string(),
+        };
+    }
+    let bounds = boundaries(value);
+    let mut res = String::new();
+    let mut i = 0;
+    while i < bounds.len() - 1 {
+        let start = bounds[i];
+        if mode == ReplaceMode::Prefix && start > 0 {
+            break;
+        }
+        let found = bounds[i + 1..].iter().rev()
+            .find(|end| (mode != ReplaceMode::Suffix || **end == value.len()) && glob::matches(pattern, &value[start..**end], false));
+        match found {
+            Some(end) => {
+                res.push_str(replacement);
+                if mode != ReplaceMode::All {
+                    res.push_str(&value[*end..]);
+                    return res;
+                }
+                i = bounds.iter().position(|b| b == end).unwrap();
+            }
+            None => {
+                res.push_str(&value[start..bounds[i + 1]]);
+                i += 1;
+            }
+        }
+    }
+    res.push_str(&value[bounds[i]..]);
+    res
+}
+
+fn change_case(value: &str, upper: bool, all: bool) -> String {
+    let convert = |s: &str| if upper { s.to_uppercase() } else { s.to_lowercase() };
+    if all {
+        return convert(value);
+    }
+    match value.chars().next() {
+        Some(c) => format!("{}{}", convert(&c.to_string()), &value[c.len_utf8()..]),
+        None => String::new(),
+    }
+}
+
+//the range of a `${name:offset:length}` in something of length len, negative numbers count from the end
+fn substring_range(len: usize, offset: i64, length: Option<i64>) -> Result<(usize, usize), String> {
+    let len = len as i64;
+    let start = if offset < 0 { len + offset } else { offset };
+    if start < 0 || start > len {
+        return Ok((0, 0));
+    }
+    let end = match length {
+        None => len,
+        Some(length) if length < 0 => len + length,
+        Some(length) => (start + length).min(len),
+    };
+    if end < start {
+        return Err(format!("{}: substring expression < 0", length.unwrap_or(0)));
+    }
+    Ok((start as usize, end as usize))
+}
+
+//collects the fields a word expands to, each with the glob pattern it makes
+struct Fields {
+    fields: Vec<(String, String)>,
+    current: String,
+    //the current field with the quoted characters escaped
+    pattern: String,
+    //set once the current field exists, even if it is empty (e.g. `""`)
+    started: bool,
+}
+
+impl Fields {
+    fn new() -> Self {
+        Fields { fields: Vec::new(), current: String::new(), pattern: String::new(), started: false }
+    }
+
+    //quoted text, which only ever matches itself
+    fn push_str(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.pattern.push_str(&glob::escape(s));
+        self.started = true;
+    }
+
+    //unquoted text, in which glob characters are special
+    fn push_unquoted(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.pattern.push_str(s);
+        self.started = true;
+    }
+
+    //the result of an unquoted expansion gets split into several fields on IFS
+    fn push_split(&mut self, s: &str, ifs: &str) {
+        for c in s.chars() {
+            if !ifs.contains(c) {
+                self.current.push(c);
+                self.pattern.push(c);
+                self.started = true;
+            } else if c.is_whitespace() {
+                self.end_field();
+            } else {
+                self.started = true;
+                self.end_field();
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.started {
+            self.split_here();
+            self.started = false;
+        }
+    }
+
+    //ends the current field even inside quotes, for the boundaries of "$@"
+    fn split_here(&mut self) {
+        self.fields.push((std::mem::take(&mut self.current), std::mem::take(&mut self.pattern)));
+        self.started = true;
+    }
+
+    fn finish(mut self) -> Vec<(String, String)> {
+        self.end_field();
+        self.fields
+    }
+}
+
+impl Shell {
+    fn get_var(&mut self, name: &str) -> String {
+        if let Some(items) = self.list_items(name) {
+            return if keeps_items_apart(name) { items.join(" ") } else { self.join_star(&items) };
+        }
+        match name {
+            "?" => self.last_status.to_string(),
+            "#" => self.positional.len().to_string(),
+            "0" => self.arg0.clone(),
+            "!" => self.last_bg_pid.map(|pid| pid.to_string()).unwrap_or_default(),
+            name if name.chars().all(|c| c.is_ascii_digit()) => {
+                let n: usize = name.parse().unwrap_or(0);
+                self.positional.get(n.wrapping_sub(1)).cloned().unwrap_or_default()
+            }
+            name if name.ends_with(']') => self.get_element(name).unwrap_or_default(),
+            name => self.vars.get(name).and_then(|var| var.value.scalar()).unwrap_or_default().to_string(),
+        }
+    }
+
+    //"$*" joins with the first character of IFS
+    fn join_star(&self, items: &[String]) -> String {
+        let ifs = self.get_ifs();
+        let separator = ifs.chars().next().map(|c| c.to_string()).unwrap_or_default();
+        items.join(&separator)
+    }
+
+    fn var_is_set(&mut self, name: &str) -> bool {
+        if let Some(items) = self.list_items(name) {
+            return !items.is_empty();
+        }
+        match name {
+            "?" | "#" | "0" => true,
+            "!" => self.last_bg_pid.is_some(),
+            name if name.chars().all(|c| c.is_ascii_digit()) => {
+                let n: usize = name.parse().unwrap_or(0);
+                n <= self.positional.len()
+            }
+            name if name.ends_with(']') => self.get_element(name).is_some(),
+            name => self.vars.get(name).and_then(|var| var.value.scalar()).is_some(),
+        }
+    }
+
+    fn expand_param<'a>(&mut self, name: &str, op: &'a ParamOp) -> ParamValue<'a> {
+        let items = self.list_items(name);
+        let is_list = items.is_some();
+        let set = self.var_is_set(name);
+        let value = self.get_var(name);
+        let values = items.unwrap_or_else(|| vec![value.clone()]);
+        let unset = |colon: bool| !set || colon && value.is_empty();
+        //the operators that change the value work on each positional parameter separately
+        let result = |values: Vec<String>| {
+            if is_list {
+                ParamValue::List(values)
+            } else {
+                ParamValue::Value(values.into_iter().next().unwrap_or_default())
+            }
+        };
+        match op {
+            ParamOp::Keys => ParamValue::List(self.array_keys(name)),
+            ParamOp::Length if is_list => ParamValue::Value(values.len().to_string()),
+            ParamOp::Length => ParamValue::Value(value.chars().count().to_string()),
+            ParamOp::Default(colon, word) if unset(*colon) => ParamValue::Word(word),
+            ParamOp::Alternative(colon, _) if unset(*colon) => ParamValue::Value(String::new()),
+            ParamOp::Alternative(_, word) => ParamValue::Word(word),
+            ParamOp::Assign(colon, word) if unset(*colon) => {
+                let value = self.expand_word_to_string(word);
+                if !parser::is_name(name) {
+                    eprintln!("josh: ${}: cannot assign in this way", name);
+                    self.expand_failed = true;
+                    return ParamValue::Value(String::new());
+                }
+                self.set_var(name, value.clone());
+                ParamValue::Value(value)
+            }
+            ParamOp::Error(colon, word) if unset(*colon) => {
+                let message = if !word.parts.is_empty() {
+                    self.expand_word_to_string(word)
+                } else if set {
+                    "parameter null".to_string()
+                } else {
+                    "parameter not set".to_string()
+                };
+                eprintln!("josh: {}: {}", name, message);
+                self.expand_failed = true;
+                //a script can't go on without the parameter
+                if !self.job_control {
+                    self.exiting = true;
+                }
+                ParamValue::Value(String::new())
+            }
+            ParamOp::Default(..) | ParamOp::Assign(..) | ParamOp::Error(..) => result(values),
+            ParamOp::RemovePrefix(longest, pattern) => {
+                let pattern = self.expand_pattern(pattern);
+                result(values.iter().map(|value| remove_prefix(value, &pattern, *longest)).collect())
+            }
+            ParamOp::RemoveSuffix(longest, pattern) => {
+                let pattern = self.expand_pattern(pattern);
+                result(values.iter().map(|value| remove_suffix(value, &pattern, *longest)).collect())
+            }
+            ParamOp::Replace { mode, pattern, replacement } => {
+                let pattern = self.expand_pattern(pattern);
+                let replacement = self.expand_word_to_string(replacement);
+                result(values.iter().map(|value| replace(value, &pattern, &replacement, *mode)).collect())
+            }
+            ParamOp::Case { upper, all } => {
+                result(values.iter().map(|value| change_case(value, *upper, *all)).collect())
+            }
+            ParamOp::Substring(offset, length) => {
+                let range = self.eval_arith_word(offset).and_then(|offset| {
+                    let length = match length {
+                        Some(length) => Some(self.eval_arith_word(length)?),
+                        None => None,
+                    };
+                    //`${@:n}` counts $0 as the first item
+                    let len = match name {
+                        "@" | "*" => values.len() + 1,
+                        _ if is_list => values.len(),
+                        _ => value.chars().count(),
+                    };
+                    substring_range(len, offset, length)
+                });
+                match range {
+                    Ok((start, end)) if is_list => {
+                        let items = match name {
+                            "@" | "*" => std::iter::once(self.arg0.clone()).chain(values).collect(),
+                            _ => values,
+                        };
+                        ParamValue::List(items.into_iter().skip(start).take(end - start).collect())
+                    }
+                    Ok((start, end)) => ParamValue::Value(value.chars().skip(start).take(end - start).collect()),
+                    Err(e) => {
+                        eprintln!("josh: {}", e);
+                        self.expand_failed = true;
+                        ParamValue::Value(String::new())
+                    }
+                }
+            }
+        }
+    }
+
+    //expands a word into a glob pattern, in which only the unquoted characters are special
+    pub fn expand_pattern(&mut self, word: &Word) -> String {
+        let mut pattern = String::new();
+        for part in &word.parts {
+            match part {
+                WordPart::Literal(s) => pattern.push_str(s),
+                WordPart::Param(_) | WordPart::ParamExp(..) | WordPart::CommandSub(_) => {
+                    let s = self.expand_part(part);
+                    pattern.push_str(&s);
+                }
+                part => {
+                    let s = self.expand_part(part);
+                    pattern.push_str(&glob::escape(&s));
+                }
+            }
+        }
+        pattern
+    }
+
+    //expands the right side of `=~`, quoted characters only match themselves
+    pub fn expand_regex(&mut self, word: &Word) -> String {
+        let mut regex = String::new();
+        for part in &word.parts {
+            match part {
+                WordPart::Literal(s) => regex.push_str(s),
+                WordPart::Param(_) | WordPart::ParamExp(..) | WordPart::CommandSub(_) | WordPart::Arith(_) => {
+                    let s = self.expand_part(part);
+                    regex.push_str(&s);
+                }
+                part => {
+                    let s = self.expand_part(part);
+                    regex.push_str(&regex::escape(&s));
+                }
+            }
+        }
+        regex
+    }
+
+    fn get_ifs(&self) -> String {
+        match self.vars.get("IFS").and_then(|var| var.value.scalar()) {
+            Some(ifs) => ifs.to_string(),
+            None => " \t\n".to_string(),
+        }
+    }
+
+    //`$(...)` runs in a forked shell whose output is read back through a pipe,
+    //without its trailing newlines
+    fn command_sub_output(&mut self, list: &List) -> String {
+        let (read, write) = match sys::pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("josh: pipe: {}", e);
+                self.expand_failed = true;
+                return String::new();
+            }
+        };
+        let pid = match sys::fork() {
+            Ok(Fork::Child) => {
+                self.enter_child();
+                sys::close(read);
+                sys::dup2(write, 1).ok();
+                sys::close(write);
+                self.run_list(list);
+                self.run_exit_trap();
+                sys::exit(self.last_status);
+            }
+            Ok(Fork::Parent(pid)) => pid,
+            Err(e) => {
+                eprintln!("josh: fork: {}", e);
+                sys::close(read);
+                sys::close(write);
+                self.expand_failed = true;
+                return String::new();
+            }
+        };
+        sys::close(write);
+        let mut output = Vec::new();
+        let mut file = unsafe { File::from_raw_fd(read) };
+        if let Err(e) = file.read_to_end(&mut output) {
+            eprintln!("josh: command substitution: {}", sys::error_message(&e));
+        }
+        drop(file);
+        let status = match sys::wait_event(pid, 0) {
+            Ok(Some(sys::WaitEvent::Done(status))) => sys::decode_status(status),
+            _ => 1,
+        };
+        self.last_status = status;
+        self.sub_status = Some(status);
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        output
+    }
+
+    //`<(...)` and `>(...)` run in a forked shell connected to a pipe, the other end of which
+    //is kept open for the command as `/dev/fd/N` until it finishes
+    fn process_sub_path(&mut self, dir: char, list: &List) -> String {
+        let (read, write) = match sys::pipe() {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                eprintln!("josh: pipe: {}", e);
+                self.expand_failed = true;
+                return String::new();
+            }
+        };
+        //the child's end of the pipe and the one left for the command
+        let (theirs, ours, child_fd): (RawFd, RawFd, RawFd) = if dir == '<' { (write, read, 1) } else { (read, write, 0) };
+        match sys::fork() {
+            Ok(Fork::Child) => {
+                self.enter_child();
+                sys::close(ours);
+                //the pipes of the other substitutions would be held open and never see the end
+                for (fd, _) in std::mem::take(&mut self.proc_subs) {
+                    sys::close(fd);
+                }
+                sys::dup2(theirs, child_fd).ok();
+                sys::close(theirs);
+                self.run_list(list);
+                self.run_exit_trap();
+                sys::exit(self.last_status);
+            }
+            Ok(Fork::Parent(pid)) => {
+                sys::close(theirs);
+                self.proc_subs.push((ours, pid));
+                format!("/dev/fd/{}", ours)
+            }
+            Err(e) => {
+                eprintln!("josh: fork: {}", e);
+                sys::close(read);
+                sys::close(write);
+                self.expand_failed = true;
+                String::new()
+            }
+        }
+    }
+
+    //closes the pipes of the process substitutions made since `mark` and waits for them
+    pub fn end_process_subs(&mut self, mark: usize) {
+        //all the pipes are closed first, a substitution may be reading from another's output
+        let subs = self.proc_subs.split_off(mark);
+        for (fd, _) in &subs {
+            sys::close(*fd);
+        }
+        for (_, pid) in subs {
+            sys::wait_event(pid, 0).ok();
+        }
+    }
+
+    fn expand_part(&mut self, part: &WordPart) -> String {
+        match part {
+            WordPart::Literal(s) | WordPart::Quoted(s) => s.clone(),
+            WordPart::DoubleQuoted(parts) => parts.iter().map(|p| self.expand_part(p)).collect(),
+            WordPart::Param(name) => self.get_var(name),
+            WordPart::ParamExp(name, op) => match self.expand_param(name, op) {
+                ParamValue::Value(s) => s,
+                ParamValue::List(items) if !keeps_items_apart(name) => self.join_star(&items),
+                ParamValue::List(items) => items.join(" "),
+                ParamValue::Word(word) => self.expand_word_to_string(word),
+            },
+            WordPart::CommandSub(list) => self.command_sub_output(list),
+            WordPart::ProcessSub(dir, list) => self.process_sub_path(*dir, list),
+            WordPart::Arith(word) => match self.eval_arith_word(word) {
+                Ok(n) => n.to_string(),
+                Err(e) => {
+                    eprintln!("josh: {}", e);
+                    self.expand_failed = true;
+                    //like bash, a script stops at a bad arithmetic expansion
+                    if !self.job_control {
+                        self.exiting = true;
+                    }
+                    String::new()
+                }
+            },
+        }
+    }
+
+    //a leading unquoted `~` or `~/` is replaced by the home directory,
+    //returns the directory and the rest of the text
+    fn split_tilde_prefix<'a>(word: &Word, lit: &'a str) -> Option<(String, &'a str)> {
+        if lit == "~" && word.parts.len() == 1 || lit.starts_with("~/") {
+            let home = expand_tilde(&"~")?.to_str().unwrap().to_string();
+            //don't make `//` out of `~/` when home is the root directory
+            let home = if home == "/" && lit.len() > 1 { String::new() } else { home };
+            return Some((home, &lit[1..]));
+        }
+        None
+    }
+
+    fn expand_into(&mut self, fields: &mut Fields, part: &WordPart, quoted: bool) {
+        match part {
+            WordPart::DoubleQuoted(parts) => {
+                //"$@" with no positional parameters makes no field at all, the field
+                //is started by whatever it expands to
+                let only_at = matches!(parts.as_slice(), [WordPart::Param(name)] | [WordPart::ParamExp(name, _)] if keeps_items_apart(name));
+                if !only_at {
+                    fields.started = true;
+                }
+                for part in parts {
+                    self.expand_into(fields, part, true);
+                }
+            }
+            WordPart::Param(name) if keeps_items_apart(name) || !quoted && self.list_items(name).is_some() => {
+                let items = self.list_items(name).unwrap_or_default();
+                self.push_list(fields, &items, quoted);
+            }
+            WordPart::ParamExp(name, op) => match self.expand_param(name, op) {
+                ParamValue::Value(s) if quoted => fields.push_str(&s),
+                ParamValue::Value(s) => {
+                    let ifs = self.get_ifs();
+                    fields.push_split(&s, &ifs);
+                }
+                ParamValue::List(items) if keeps_items_apart(name) || !quoted => self.push_list(fields, &items, quoted),
+                ParamValue::List(items) => {
+                    let s = self.join_star(&items);
+                    fields.push_str(&s);
+                }
+                ParamValue::Word(word) => {
+                    //unquoted the operand is split like the result of any other expansion
+                    let ifs = self.get_ifs();
+                    for part in &word.parts {
+                        match part {
+                            WordPart::Literal(s) if !quoted => fields.push_split(s, &ifs),
+                            part => self.expand_into(fields, part, quoted),
+                        }
+                    }
+                }
+            },
+            WordPart::Literal(s) if !quoted => fields.push_unquoted(s),
+            WordPart::Param(_) | WordPart::CommandSub(_) | WordPart::Arith(_) if !quoted => {
+                let s = self.expand_part(part);
+                let ifs = self.get_ifs();
+                fields.push_split(&s, &ifs);
+            }
+            part => {
+                let s = self.expand_part(part);
+                fields.push_str(&s);
+            }
+        }
+    }
+
+    //the positional parameters or a list like them, as separate fields
+    fn push_list(&mut self, fields: &mut Fields, items: &[String], quoted: bool) {
+        let ifs = self.get_ifs();
+        for (i, item) in items.iter().enumerate() {
+            if quoted {
+                if i > 0 {
+                    fields.split_here();
+                }
+                fields.push_str(item);
+            } else {
+                if i > 0 {
+                    fields.end_field();
+                }
+                fields.push_split(item, &ifs);
+            }
+        }
+    }
+
+    pub fn expand_word(&mut self, word: &Word) -> Vec<String> {
+        let mut fields = Fields::new();
+        for (i, part) in word.parts.iter().enumerate() {
+            match part {
+                WordPart::Literal(s) if i == 0 => {
+                    match Shell::split_tilde_prefix(word, s) {
+                        Some((home, rest)) => {
+                            fields.push_str(&home);
+                            fields.push_unquoted(rest);
+                        }
+                        None => fields.push_unquoted(s),
+                    }
+                }
+                part => self.expand_into(&mut fields, part, false),
+            }
+        }
+        let fields = fields.finish();
+        self.expand_pathnames(fields)
+    }
+
+    //replaces fields that are glob patterns by the paths they match
+    fn expand_pathnames(&mut self, fields: Vec<(String, String)>) -> Vec<String> {
+        let mut res: Vec<String> = Vec::new();
+        for (field, pattern) in fields {
+            if self.option("noglob") || !glob::is_pattern(&pattern) {
+                res.push(field);
+                continue;
+            }
+            let options = glob::Options { dotglob: self.option("dotglob"), nocase: self.option("nocaseglob") };
+            let paths = glob::expand(&pattern, &options);
+            if !paths.is_empty() {
+                res.extend(paths);
+            } else if self.option("failglob") {
+                eprintln!("josh: no match: {}", field);
+                self.expand_failed = true;
+            } else if !self.option("nullglob") {
+                res.push(field);
+            }
+        }
+        res
+    }
+
+    pub fn expand_words(&mut self, words: &[Word]) -> Vec<String> {
+        let mut res: Vec<String> = Vec::new();
+        for word in words {
+            res.extend(self.expand_word(word));
+        }
+        res
+    }
+
+    //expansion without field splitting, for things like assignments
+    pub fn expand_word_to_string(&mut self, word: &Word) -> String {
+        let mut res = String::new();
+        for (i, part) in word.parts.iter().enumerate() {
+            match part {
+                WordPart::Literal(s) if i == 0 => match Shell::split_tilde_prefix(word, s) {
+                    Some((home, rest)) => {
+                        res.push_str(&home);
+                        res.push_str(rest);
+                    }
+                    None => res.push_str(s),
+                },
+                part => res.push_str(&self.expand_part(part)),
+            }
+        }
+        res
+    }
+}
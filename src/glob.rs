@@ -0,0 +1,278 @@
+//glob patterns, for pathname expansion and the pattern matching forms of expansions
+//
+//a pattern is ordinary text in which `*`, `?` and `[...]` are special, quoted characters are
+//escaped with a backslash so that they only match themselves
+use std::path::Path;
+
+//escapes the characters that would otherwise be special in a pattern
+pub fn escape(s: &str) -> String {
+    let mut res = String::new();
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+fn unescape(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.extend(chars.next()),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+//whether the pattern has anything in it that is not matched literally,
+//a `[` without a closing `]` is just a character
+pub fn is_pattern(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if bracket_end(&chars[i..]).is_some() => return true,
+            _ => (),
+        }
+        i += 1;
+    }
+    false
+}
+
+//the length of the bracket expression at the start of p, including both brackets
+fn bracket_end(p: &[char]) -> Option<usize> {
+    let mut i = 1;
+    if matches!(p.get(i), Some('!') | Some('^')) {
+        i += 1;
+    }
+    //a `]` right at the start is part of the set
+    if p.get(i) == Some(&']') {
+        i += 1;
+    }
+    while i < p.len() {
+        match p[i] {
+            ']' => return Some(i + 1),
+            '\\' => i += 2,
+            '[' if p.get(i + 1) == Some(&':') => {
+                let close = (i + 2..p.len().saturating_sub(1)).find(|j| p[*j] == ':' && p[*j + 1] == ']');
+                match close {
+                    Some(j) => i = j + 2,
+                    None => i += 1,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        "cntrl" => c.is_control(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        _ => false,
+    }
+}
+
+fn same_char(a: char, b: char, nocase: bool) -> bool {
+    a == b || nocase && a.to_lowercase().eq(b.to_lowercase())
+}
+
+//matches c against the bracket expression p, which bracket_end has already checked
+fn bracket_matches(p: &[char], c: char, nocase: bool) -> bool {
+    let end = p.len() - 1;
+    let mut i = 1;
+    let negated = matches!(p[i], '!' | '^');
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < end {
+        if p[i] == '[' && p.get(i + 1) == Some(&':') {
+            if let Some(j) = (i + 2..end).find(|j| p[*j] == ':' && p[*j + 1] == ']') {
+                let class: String = p[i + 2..j].iter().collect();
+                matched |= class_matches(&class, c);
+                i = j + 2;
+                continue;
+            }
+        }
+        let mut low = p[i];
+        if low == '\\' && i + 1 < end {
+            i += 1;
+            low = p[i];
+        }
+        i += 1;
+        if i + 1 < end && p[i] == '-' && p[i + 1] != ']' {
+            let mut high = p[i + 1];
+            i += 2;
+            if high == '\\' && i < end {
+                high = p[i];
+                i += 1;
+            }
+            matched |= (low..=high).contains(&c)
+                || nocase && c.to_lowercase().chain(c.to_uppercase()).any(|c| (low..=high).contains(&c));
+        } else {
+            matched |= same_char(low, c, nocase);
+        }
+    }
+    matched != negated
+}
+
+//the length of the part of the pattern at p[0] that matches exactly one character, if c matches it
+fn match_one(p: &[char], c: char, nocase: bool) -> Option<usize> {
+    match p[0] {
+        '?' => Some(1),
+        '[' => match bracket_end(p) {
+            Some(len) => bracket_matches(&p[..len], c, nocase).then_some(len),
+            None => same_char('[', c, nocase).then_some(1),
+        },
+        '\\' if p.len() > 1 => same_char(p[1], c, nocase).then_some(2),
+        pc => same_char(pc, c, nocase).then_some(1),
+    }
+}
+
+fn match_chars(p: &[char], t: &[char], nocase: bool) -> bool {
+    let (mut pi, mut ti) = (0, 0);
+    //where to resume after the last `*` if what follows it fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() {
+            if p[pi] == '*' {
+                pi += 1;
+                backtrack = Some((pi, ti));
+                continue;
+            }
+            if let Some(len) = match_one(&p[pi..], t[ti], nocase) {
+                pi += len;
+                ti += 1;
+                continue;
+            }
+        }
+        match backtrack {
+            Some((star_pi, star_ti)) => {
+                pi = star_pi;
+                ti = star_ti + 1;
+                backtrack = Some((star_pi, star_ti + 1));
+            }
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+pub fn matches(pattern: &str, text: &str, nocase: bool) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    match_chars(&p, &t, nocase)
+}
+
+pub struct Options {
+    //let wildcards match names starting with `.`
+    pub dotglob: bool,
+    pub nocase: bool,
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+//the names in a directory that wildcards may match, hidden ones only if dotglob is set
+//or the pattern itself starts with a `.`
+fn entries(dir: &str, pattern: &str, options: &Options) -> Vec<String> {
+    let path = if dir.is_empty() { "." } else { dir };
+    let read = match std::fs::read_dir(path) {
+        Ok(read) => read,
+        Err(_) => return Vec::new(),
+    };
+    let mut names: Vec<String> = read
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || options.dotglob || pattern.starts_with('.') || pattern.starts_with("\\."))
+        .collect();
+    names.sort();
+    names
+}
+
+fn walk(dir: &str, components: &[&str], options: &Options, results: &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => {
+            results.push(dir.to_string());
+            return;
+        }
+    };
+    if *component == "**" {
+        //`**` matches any number of directories, including none
+        if rest.is_empty() {
+            for name in entries(dir, "", options) {
+                let path = join(dir, &name);
+                results.push(path.clone());
+                if Path::new(&path).is_dir() && !Path::new(&path).is_symlink() {
+                    walk(&path, components, options, results);
+                }
+            }
+            return;
+        }
+        walk(dir, rest, options, results);
+        for name in entries(dir, "", options) {
+            let path = join(dir, &name);
+            if Path::new(&path).is_dir() && !Path::new(&path).is_symlink() {
+                walk(&path, components, options, results);
+            }
+        }
+    } else if !is_pattern(component) {
+        let path = join(dir, &unescape(component));
+        let found = if rest.is_empty() { Path::new(&path).symlink_metadata().is_ok() } else { Path::new(&path).is_dir() };
+        if found {
+            walk(&path, rest, options, results);
+        }
+    } else {
+        for name in entries(dir, component, options) {
+            if matches(component, &name, options.nocase) {
+                let path = join(dir, &name);
+                if rest.is_empty() || Path::new(&path).is_dir() {
+                    walk(&path, rest, options, results);
+                }
+            }
+        }
+    }
+}
+
+//the sorted paths matching a pattern
+pub fn expand(pattern: &str, options: &Options) -> Vec<String> {
+    let root = if pattern.starts_with('/') { "/" } else { "" };
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let mut results: Vec<String> = Vec::new();
+    walk(root, &components, options, &mut results);
+    //a trailing slash only matches directories
+    if pattern.ends_with('/') {
+        results = results.into_iter()
+            .filter(|path| Path::new(path).is_dir())
+            .map(|path| format!("{}/", path))
+            .collect();
+    }
+    results.sort();
+    results.dedup();
+    results
+}
@@ -0,0 +1,421 @@
+//background and stopped jobs, and the builtins that manage them
+use crate::sys::{self, WaitEvent};
+use crate::{write_out, Shell};
+
+pub struct Job {
+    pub id: usize,
+    pub pgid: libc::pid_t,
+    //each process with its raw wait status once it has finished
+    pub procs: Vec<(libc::pid_t, Option<libc::c_int>)>,
+    pub stopped: bool,
+    pub text: String,
+    //the terminal modes the job had when it was stopped, put back by `fg`
+    pub termios: Option<libc::termios>,
+    //whether the last change of state has been reported
+    pub notified: bool,
+}
+
+impl Job {
+    pub fn new(pgid: libc::pid_t, pids: Vec<libc::pid_t>, text: &str) -> Self {
+        Job {
+            id: 0,
+            pgid,
+            procs: pids.into_iter().map(|pid| (pid, None)).collect(),
+            stopped: false,
+            text: text.to_string(),
+            termios: None,
+            notified: false,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.procs.iter().all(|(_, status)| status.is_some())
+    }
+
+    //the exit status of the job, which is that of its last process unless pipefail is set
+    pub fn status(&self, pipefail: bool) -> i32 {
+        let statuses: Vec<i32> = self.procs.iter().map(|(_, status)| sys::decode_status(status.unwrap_or(0))).collect();
+        if pipefail {
+            statuses.iter().rev().find(|s| **s != 0).copied().unwrap_or(0)
+        } else {
+            statuses.last().copied().unwrap_or(0)
+        }
+    }
+
+    fn state(&self) -> String {
+        if !self.done() {
+            return if self.stopped { "Stopped" } else { "Running" }.to_string();
+        }
+        let status = self.procs.last().and_then(|(_, status)| *status).unwrap_or(0);
+        if libc::WIFSIGNALED(status) {
+            sys::signal_description(libc::WTERMSIG(status))
+        } else if libc::WEXITSTATUS(status) != 0 {
+            format!("Exit {}", libc::WEXITSTATUS(status))
+        } else {
+            "Done".to_string()
+        }
+    }
+
+    fn record(&mut self, pid: libc::pid_t, event: WaitEvent) {
+        match event {
+            WaitEvent::Done(status) => {
+                if let Some(proc) = self.procs.iter_mut().find(|(p, _)| *p == pid) {
+                    proc.1 = Some(status);
+                }
+                if self.done() {
+                    self.notified = false;
+                }
+            }
+            WaitEvent::Stopped => {
+                if !self.stopped {
+                    self.stopped = true;
+                    self.notified = false;
+                }
+            }
+            WaitEvent::Continued => self.stopped = false,
+        }
+    }
+
+    //waits until every process has finished or, if untraced, one of them stops
+    fn wait(&mut self, untraced: bool) {
+        let flags = if untraced { libc::WUNTRACED } else { 0 };
+        for i in 0..self.procs.len() {
+            let pid = self.procs[i].0;
+            while self.procs[i].1.is_none() {
+                match sys::wait_event(pid, flags) {
+                    Ok(Some(event)) => self.record(pid, event),
+                    //not our child any more, there is nothing to wait for
+                    Ok(None) | Err(_) => self.procs[i].1 = Some(127 << 8),
+                }
+                if self.stopped {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Shell {
+    //the job table is kept in order of use, so the current job `%+` is the last one
+    fn current_job(&self, offset: usize) -> Option<usize> {
+        self.jobs.len().checked_sub(1 + offset)
+    }
+
+    //finds the job a `%n`, `%%`, `%+`, `%-` or `%prefix` spec refers to
+    fn find_job(&self, spec: &str) -> Result<usize, String> {
+        let found = match spec.strip_prefix('%').unwrap_or(spec) {
+            "" | "%" | "+" => self.current_job(0),
+            "-" => self.current_job(1),
+            name => match name.parse::<usize>() {
+                Ok(id) => self.jobs.iter().position(|job| job.id == id),
+                Err(_) => {
+                    let mut matches = self.jobs.iter().enumerate().filter(|(_, job)| job.text.starts_with(name));
+                    match (matches.next(), matches.next()) {
+                        (Some(_), Some(_)) => return Err(format!("{}: ambiguous job spec", spec)),
+                        (found, _) => found.map(|(i, _)| i),
+                    }
+                }
+            },
+        };
+        found.ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    //adds a job to the table as the current job
+    pub fn add_job(&mut self, mut job: Job) -> usize {
+        job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    //checks on every job without blocking
+    pub fn update_jobs(&mut self) {
+        for job in &mut self.jobs {
+            for i in 0..job.procs.len() {
+                let pid = job.procs[i].0;
+                while job.procs[i].1.is_none() {
+                    let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+                    match sys::wait_event(pid, flags) {
+                        Ok(Some(event)) => job.record(pid, event),
+                        Ok(None) => break,
+                        Err(_) => job.procs[i].1 = Some(127 << 8),
+                    }
+                }
+            }
+        }
+    }
+
+    fn job_marker(&self, index: usize) -> char {
+        if Some(index) == self.current_job(0) {
+            '+'
+        } else if Some(index) == self.current_job(1) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    //a line of `jobs` output, with the process group id if long
+    fn format_job(&self, index: usize, long: bool) -> String {
+        let job = &self.jobs[index];
+        let text = if job.done() || job.stopped { job.text.clone() } else { format!("{} &", job.text) };
+        let pgid = if long { format!(" {}", job.pgid) } else { String::new() };
+        format!("[{}]{}{}  {:24}{}", job.id, self.job_marker(index), pgid, job.state(), text)
+    }
+
+    //reports jobs that finished or stopped since the last prompt, and forgets the finished ones
+    pub fn notify_jobs(&mut self) {
+        self.update_jobs();
+        for i in 0..self.jobs.len() {
+            if !self.jobs[i].notified {
+                eprintln!("{}", self.format_job(i, false));
+                self.jobs[i].notified = true;
+            }
+        }
+        self.jobs.retain(|job| !job.done());
+    }
+
+    //gives the terminal to the job and waits for it to finish or stop, returning its status
+    pub fn wait_foreground(&mut self, mut job: Job) -> i32 {
+        if self.job_control {
+            sys::tcsetpgrp(0, job.pgid);
+        }
+        job.wait(self.job_control);
+        if self.job_control {
+            sys::tcsetpgrp(0, sys::getpgrp());
+            if job.stopped {
+                job.termios = sys::get_termios(0);
+            }
+            if let Some(termios) = &self.shell_termios {
+                sys::set_termios(0, termios);
+            }
+        }
+        if !job.stopped {
+            if let Some((_, Some(status))) = job.procs.last() {
+                if libc::WIFSIGNALED(*status) {
+                    self.report_signal(*status);
+                }
+            }
+            return job.status(self.option("pipefail"));
+        }
+        //a job brought back with `fg` keeps its number
+        job.notified = true;
+        if job.id == 0 {
+            self.add_job(job);
+        } else {
+            self.jobs.push(job);
+        }
+        eprintln!("\n{}", self.format_job(self.jobs.len() - 1, false));
+        128 + libc::SIGTSTP
+    }
+
+    //says why a foreground job died, like a shell does for anything but ctrl-c and broken pipes
+    fn report_signal(&mut self, status: libc::c_int) {
+        match libc::WTERMSIG(status) {
+            libc::SIGINT => {
+                //the job got the ctrl-c instead of the shell, but it was meant for both
+                if self.job_control {
+                    println!();
+                    self.interrupted = true;
+                }
+            }
+            libc::SIGPIPE => (),
+            sig => {
+                let core = if libc::WCOREDUMP(status) { " (core dumped)" } else { "" };
+                eprintln!("{}{}", sys::signal_description(sig), core);
+            }
+        }
+    }
+
+    pub fn jobs_builtin(&mut self, argv: &[String]) -> i32 {
+        let mut long = false;
+        let mut pids_only = false;
+        let mut specs: Vec<&String> = Vec::new();
+        for arg in argv {
+            match arg.as_str() {
+                "-l" => long = true,
+                "-p" => pids_only = true,
+                arg if arg.starts_with('-') && arg.len() > 1 => {
+                    eprintln!("josh: jobs: {}: invalid option", arg);
+                    return 2;
+                }
+                _ => specs.push(arg),
+            }
+        }
+        self.update_jobs();
+        let mut indices: Vec<usize> = Vec::new();
+        let mut status = 0;
+        if specs.is_empty() {
+            indices = (0..self.jobs.len()).collect();
+            indices.sort_by_key(|i| self.jobs[*i].id);
+        }
+        for spec in specs {
+            match self.find_job(spec) {
+                Ok(i) => indices.push(i),
+                Err(e) => {
+                    eprintln!("josh: jobs: {}", e);
+                    status = 1;
+                }
+            }
+        }
+        for i in indices {
+            let line = if pids_only { self.jobs[i].pgid.to_string() } else { self.format_job(i, long) };
+            if write_out("jobs", &format!("{}\n", line)) != 0 {
+                return 1;
+            }
+            self.jobs[i].notified = true;
+        }
+        self.jobs.retain(|job| !job.done() || !job.notified);
+        status
+    }
+
+    //`fg` and `bg`
+    pub fn resume_builtin(&mut self, command: &str, argv: &[String]) -> i32 {
+        if !self.job_control {
+            eprintln!("josh: {}: no job control", command);
+            return 1;
+        }
+        self.update_jobs();
+        let spec = argv.first().map(|s| s.as_str()).unwrap_or("%+");
+        let index = match self.find_job(spec) {
+            Ok(i) if !self.jobs[i].done() => i,
+            Ok(_) => {
+                eprintln!("josh: {}: job has terminated", command);
+                return 1;
+            }
+            Err(e) => {
+                let e = if argv.is_empty() { "current: no such job".to_string() } else { e };
+                eprintln!("josh: {}: {}", command, e);
+                return 1;
+            }
+        };
+        let mut job = self.jobs.remove(index);
+        if command == "fg" {
+            write_out("fg", &format!("{}\n", job.text));
+            if let Some(termios) = &job.termios {
+                sys::set_termios(0, termios);
+            }
+            job.stopped = false;
+            sys::kill(-job.pgid, libc::SIGCONT).ok();
+            self.wait_foreground(job)
+        } else {
+            job.stopped = false;
+            job.notified = true;
+            sys::kill(-job.pgid, libc::SIGCONT).ok();
+            let status = write_out("bg", &format!("[{}]+ {} &\n", job.id, job.text));
+            self.jobs.push(job);
+            status
+        }
+    }
+
+    pub fn wait_builtin(&mut self, argv: &[String]) -> i32 {
+        if argv.is_empty() {
+            for job in &mut self.jobs {
+                job.wait(false);
+            }
+            self.jobs.clear();
+            return 0;
+        }
+        let mut status = 0;
+        for arg in argv {
+            let index = if arg.starts_with('%') {
+                match self.find_job(arg) {
+                    Ok(i) => Some(i),
+                    Err(e) => {
+                        eprintln!("josh: wait: {}", e);
+                        status = 127;
+                        continue;
+                    }
+                }
+            } else {
+                match arg.parse::<libc::pid_t>() {
+                    Ok(pid) => self.jobs.iter().position(|job| job.procs.iter().any(|(p, _)| *p == pid)),
+                    Err(_) => {
+                        eprintln!("josh: wait: `{}': not a pid or valid job spec", arg);
+                        status = 2;
+                        continue;
+                    }
+                }
+            };
+            match index {
+                Some(i) => {
+                    let mut job = self.jobs.remove(i);
+                    job.wait(false);
+                    status = job.status(self.option("pipefail"));
+                }
+                None => {
+                    eprintln!("josh: wait: pid {} is not a child of this shell", arg);
+                    status = 127;
+                }
+            }
+        }
+        status
+    }
+
+    pub fn kill_builtin(&mut self, argv: &[String]) -> i32 {
+        let mut sig = libc::SIGTERM;
+        let mut args = argv;
+        match args.first().map(|s| s.as_str()) {
+            None => {
+                eprintln!("josh: kill: usage: kill [-s sigspec | -sigspec] pid | jobspec ... or kill -l");
+                return 2;
+            }
+            Some("-l") => {
+                let text: String = sys::SIGNALS.iter().map(|(name, number)| format!("{:2}) SIG{}\n", number, name)).collect();
+                return write_out("kill", &text);
+            }
+            Some("-s") => {
+                let name = args.get(1).map(|s| s.as_str()).unwrap_or("");
+                match sys::signal_number(name) {
+                    Some(n) => sig = n,
+                    None => {
+                        eprintln!("josh: kill: {}: invalid signal specification", name);
+                        return 1;
+                    }
+                }
+                args = &args[2.min(args.len())..];
+            }
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => {
+                match sys::signal_number(&arg[1..]) {
+                    Some(n) => sig = n,
+                    None => {
+                        eprintln!("josh: kill: {}: invalid signal specification", &arg[1..]);
+                        return 1;
+                    }
+                }
+                args = &args[1..];
+            }
+            _ => (),
+        }
+        let mut status = 0;
+        for arg in args {
+            let (pid, stopped) = if arg.starts_with('%') {
+                match self.find_job(arg) {
+                    Ok(i) => (-self.jobs[i].pgid, self.jobs[i].stopped),
+                    Err(e) => {
+                        eprintln!("josh: kill: {}", e);
+                        status = 1;
+                        continue;
+                    }
+                }
+            } else {
+                match arg.parse::<libc::pid_t>() {
+                    Ok(pid) => (pid, false),
+                    Err(_) => {
+                        eprintln!("josh: kill: {}: arguments must be process or job IDs", arg);
+                        status = 1;
+                        continue;
+                    }
+                }
+            };
+            if let Err(e) = sys::kill(pid, sig) {
+                eprintln!("josh: kill: ({}) - {}", arg, sys::error_message(&e));
+                status = 1;
+            } else if stopped && sig != libc::SIGKILL && sig != libc::SIGCONT {
+                //a stopped job would only see the signal once it is continued
+                sys::kill(pid, libc::SIGCONT).ok();
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,990 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::parser::{self, List};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    Literal(String),
+    //single quoted, `$'...'` or backslash escaped text, taken exactly as is
+    Quoted(String),
+    DoubleQuoted(Vec<WordPart>),
+    Param(String),
+    //`${name<op>...}`
+    ParamExp(String, Box<ParamOp>),
+    CommandSub(List),
+    //`$((...))`
+    Arith(Word),
+    //`<(...)` or `>(...)`, with the `<` or `>`
+    ProcessSub(char, List),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaceMode {
+    First,
+    All,
+    //`${name/#pattern/rep}` and `${name/%pattern/rep}`, the match has to be at the start or end
+    Prefix,
+    Suffix,
+}
+
+//the operators of `${...}` expansions, the bools are true for the `:` forms, where a
+//variable that is set but empty counts as unset, or for the longest match in trims
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamOp {
+    //`${#name}`
+    Length,
+    //`${name:-word}`
+    Default(bool, Word),
+    //`${name:=word}`
+    Assign(bool, Word),
+    //`${name:?message}`
+    Error(bool, Word),
+    //`${name:+word}`
+    Alternative(bool, Word),
+    //`${name#pattern}` and `${name##pattern}`
+    RemovePrefix(bool, Word),
+    //`${name%pattern}` and `${name%%pattern}`
+    RemoveSuffix(bool, Word),
+    Replace {
+        mode: ReplaceMode,
+        pattern: Word,
+        replacement: Word,
+    },
+    //`${name:offset:length}`, both arithmetic
+    Substring(Word, Option<Word>),
+    //`${name^}`, `${name^^}`, `${name,}` and `${name,,}`, all changes every character
+    Case {
+        upper: bool,
+        all: bool,
+    },
+    //`${!name[@]}`, the indexes or keys of an array
+    Keys,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    //the text of the word if it is nothing but unquoted characters
+    pub fn as_literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(s)] => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    AndDGreat,
+    AndIf,
+    OrIf,
+    DGreat,
+    AndGreat,
+    GreatAnd,
+    LessAnd,
+    //`<<`, `<<-` and `<<<`
+    DLess,
+    DLessDash,
+    TLess,
+    Pipe,
+    Amp,
+    Semi,
+    //`;;`, `;&` and `;;&`, which end the items of a `case`
+    DSemi,
+    SemiAnd,
+    DSemiAnd,
+    Less,
+    Great,
+    LParen,
+    RParen,
+}
+
+//longest operators first so that e.g. `&&` wins over `&`
+const OPERATORS: &[(&str, Op)] = &[
+    ("&>>", Op::AndDGreat),
+    (";;&", Op::DSemiAnd),
+    ("&&", Op::AndIf),
+    (";;", Op::DSemi),
+    (";&", Op::SemiAnd),
+    ("||", Op::OrIf),
+    (">>", Op::DGreat),
+    ("&>", Op::AndGreat),
+    (">&", Op::GreatAnd),
+    ("<<<", Op::TLess),
+    ("<<-", Op::DLessDash),
+    ("<<", Op::DLess),
+    ("<&", Op::LessAnd),
+    ("|", Op::Pipe),
+    ("&", Op::Amp),
+    (";", Op::Semi),
+    ("<", Op::Less),
+    (">", Op::Great),
+    ("(", Op::LParen),
+    (")", Op::RParen),
+];
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = OPERATORS.iter().find(|(_, op)| op == self).unwrap().0;
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Op(Op),
+    //the `2` in `2>file`
+    IoNumber(i32),
+    Newline,
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word.as_literal().unwrap_or("word")),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::IoNumber(n) => write!(f, "{}", n),
+            Token::Newline => write!(f, "newline"),
+            Token::Eof => write!(f, "EOF"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    //the input stopped in the middle of something, more input could complete it
+    Incomplete(String),
+    Syntax(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Incomplete(msg) | ParseError::Syntax(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+fn is_metachar(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn push_literal(parts: &mut Vec<WordPart>, lit: &mut String) {
+    if !lit.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(lit)));
+    }
+}
+
+pub struct Lexer<'a> {
+    chars: Vec<char>,
+    //marks the characters spliced in by alias expansion, which are not part of the source text
+    inserted: Vec<bool>,
+    pos: usize,
+    //where the last token read started, for recovering the source text of a command
+    pub token_start: usize,
+    aliases: &'a HashMap<String, String>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &str, aliases: &'a HashMap<String, String>) -> Self {
+        let chars: Vec<char> = src.chars().collect();
+        Lexer {
+            inserted: vec![false; chars.len()],
+            chars,
+            pos: 0,
+            token_start: 0,
+            aliases,
+        }
+    }
+
+    pub fn text(&self, start: usize, end: usize) -> String {
+        self.chars[start..end].iter()
+            .zip(&self.inserted[start..end])
+            .filter(|(_, inserted)| !**inserted)
+            .map(|(c, _)| c)
+            .collect()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    //whether the next character after any blanks is c, without reading a token
+    pub fn next_char_is(&self, c: char) -> bool {
+        self.chars[self.pos..].iter().find(|c| **c != ' ' && **c != '\t') == Some(&c)
+    }
+
+    //splices text in at the current position, used for alias expansion
+    pub fn insert(&mut self, text: &str) {
+        let pos = self.pos;
+        self.chars.splice(pos..pos, text.chars());
+        self.inserted.splice(pos..pos, text.chars().map(|_| true));
+    }
+
+    fn is_line_continuation(&self) -> bool {
+        self.peek_char() == Some('\\') && self.chars.get(self.pos + 1) == Some(&'\n')
+    }
+
+    //skips a backslash-newline, which needs another line if nothing follows it
+    fn skip_line_continuation(&mut self) -> Result<(), ParseError> {
+        self.pos += 2;
+        if self.pos == self.chars.len() {
+            return Err(ParseError::Incomplete("EOF after line continuation".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        loop {
+            match self.peek_char() {
+                Some(' ') | Some('\t') => self.pos += 1,
+                _ if self.is_line_continuation() => self.skip_line_continuation()?,
+                _ => break,
+            }
+        }
+        self.token_start = self.pos;
+        match self.peek_char() {
+            None => Ok(Token::Eof),
+            Some('#') => {
+                while let Some(c) = self.peek_char() {
+                    if c == '\n' { break; }
+                    self.pos += 1;
+                }
+                self.next_token()
+            }
+            Some('\n') => {
+                self.pos += 1;
+                Ok(Token::Newline)
+            }
+            _ if self.at_process_sub() => Ok(Token::Word(self.read_word()?)),
+            Some(c) if is_metachar(c) => Ok(Token::Op(self.read_operator())),
+            Some(c) if c.is_ascii_digit() => {
+                let digits = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).count();
+                match self.chars.get(self.pos + digits) {
+                    Some('<') | Some('>') => {
+                        let number: String = self.chars[self.pos..self.pos + digits].iter().collect();
+                        if let Ok(n) = number.parse() {
+                            self.pos += digits;
+                            return Ok(Token::IoNumber(n));
+                        }
+                        Ok(Token::Word(self.read_word()?))
+                    }
+                    _ => Ok(Token::Word(self.read_word()?)),
+                }
+            }
+            Some(_) => Ok(Token::Word(self.read_word()?)),
+        }
+    }
+
+    fn read_operator(&mut self) -> Op {
+        for (text, op) in OPERATORS {
+            let len = text.chars().count();
+            if self.pos + len <= self.chars.len() && self.chars[self.pos..self.pos + len].iter().copied().eq(text.chars()) {
+                self.pos += len;
+                return *op;
+            }
+        }
+        unreachable!("read_operator called on a non-operator character")
+    }
+
+    fn at_process_sub(&self) -> bool {
+        matches!(self.peek_char(), Some('<') | Some('>')) && self.chars.get(self.pos + 1) == Some(&'(')
+    }
+
+    fn read_word(&mut self) -> Result<Word, ParseError> {
+        let mut parts: Vec<WordPart> = Vec::new();
+        let mut lit = String::new();
+        while let Some(c) = self.peek_char() {
+            match c {
+                c if self.at_process_sub() => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 2;
+                    let text = self.read_balanced()?;
+                    parts.push(WordPart::ProcessSub(c, self.parse_substitution(&text)?));
+                }
+                c if is_metachar(c) => break,
+                '"' => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    let inner = self.read_double_quoted()?;
+                    parts.push(WordPart::DoubleQuoted(inner));
+                }
+                '\'' => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    let text = self.read_single_quoted()?;
+                    parts.push(WordPart::Quoted(text));
+                }
+                '\\' => {
+                    if self.is_line_continuation() {
+                        self.skip_line_continuation()?;
+                        continue;
+                    }
+                    self.pos += 1;
+                    match self.peek_char() {
+                        Some(c) => {
+                            push_literal(&mut parts, &mut lit);
+                            parts.push(WordPart::Quoted(c.to_string()));
+                            self.pos += 1;
+                        }
+                        None => lit.push('\\'),
+                    }
+                }
+                '$' if self.chars.get(self.pos + 1) == Some(&'\'') => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 2;
+                    let text = self.read_ansi_c()?;
+                    parts.push(WordPart::Quoted(text));
+                }
+                '$' => {
+                    self.pos += 1;
+                    match self.read_dollar()? {
+                        Some(part) => {
+                            push_literal(&mut parts, &mut lit);
+                            parts.push(part);
+                        }
+                        None => lit.push('$'),
+                    }
+                }
+                '`' => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    parts.push(self.read_backquoted(false)?);
+                }
+                c => {
+                    lit.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        push_literal(&mut parts, &mut lit);
+        Ok(Word { parts })
+    }
+
+    fn read_double_quoted(&mut self) -> Result<Vec<WordPart>, ParseError> {
+        self.read_quoted_text(Some('"'))
+    }
+
+    //text where only expansions and backslashes are special, up to end or EOF if end is None
+    fn read_quoted_text(&mut self, end: Option<char>) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts: Vec<WordPart> = Vec::new();
+        let mut lit = String::new();
+        loop {
+            match self.peek_char() {
+                None if end.is_none() => break,
+                None => return Err(ParseError::Incomplete("EOF while scanning string literal".to_string())),
+                Some(c) if Some(c) == end => {
+                    self.pos += 1;
+                    break;
+                }
+                //inside double quotes a backslash only escapes the characters that are special there
+                Some('\\') => {
+                    match self.chars.get(self.pos + 1) {
+                        Some('\n') => (),
+                        //`\"` keeps its backslash in a here-document
+                        Some(&c) if matches!(c, '$' | '`' | '\\') || c == '"' && end.is_some() => lit.push(c),
+                        _ => {
+                            lit.push('\\');
+                            self.pos += 1;
+                            continue;
+                        }
+                    }
+                    self.pos += 2;
+                }
+                Some('$') => {
+                    self.pos += 1;
+                    match self.read_dollar()? {
+                        Some(part) => {
+                            push_literal(&mut parts, &mut lit);
+                            parts.push(part);
+                        }
+                        None => lit.push('$'),
+                    }
+                }
+                Some('`') => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    parts.push(self.read_backquoted(end == Some('"'))?);
+                }
+                Some(c) => {
+                    lit.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        push_literal(&mut parts, &mut lit);
+        Ok(parts)
+    }
+
+    //reads the `((...))` of an arithmetic command if one comes next
+    pub fn read_double_paren(&mut self) -> Result<Option<String>, ParseError> {
+        while let Some(' ') | Some('\t') = self.peek_char() {
+            self.pos += 1;
+        }
+        if self.peek_char() != Some('(') || self.chars.get(self.pos + 1) != Some(&'(') {
+            return Ok(None);
+        }
+        self.pos += 2;
+        self.read_arith_body().map(Some)
+    }
+
+    //after an already read `(`, reads the rest of a `((...))` if the next character is the second `(`
+    pub fn read_arith_rest(&mut self) -> Result<Option<String>, ParseError> {
+        if self.peek_char() != Some('(') {
+            return Ok(None);
+        }
+        self.pos += 1;
+        self.read_arith_body().map(Some)
+    }
+
+    //everything up to the `))` closing an arithmetic expression
+    fn read_arith_body(&mut self) -> Result<String, ParseError> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.peek_char() {
+                None => return Err(ParseError::Incomplete("EOF while looking for matching `))'".to_string())),
+                Some(')') if depth == 0 && self.chars.get(self.pos + 1) == Some(&')') => {
+                    self.pos += 2;
+                    return Ok(text);
+                }
+                Some(c) => {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => (),
+                    }
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn read_single_quoted(&mut self) -> Result<String, ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.peek_char() {
+                None => return Err(ParseError::Incomplete("EOF while scanning string literal".to_string())),
+                Some('\'') => {
+                    self.pos += 1;
+                    return Ok(text);
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    //reads at most max digits in the given radix, None if there are none
+    fn read_number(&mut self, radix: u32, max: usize) -> Option<u32> {
+        let mut value: Option<u32> = None;
+        for _ in 0..max {
+            match self.peek_char().and_then(|c| c.to_digit(radix)) {
+                Some(digit) => {
+                    value = Some(value.unwrap_or(0) * radix + digit);
+                    self.pos += 1;
+                }
+                None => break,
+            }
+        }
+        value
+    }
+
+    //the body of a `$'...'` string, with C style escapes
+    fn read_ansi_c(&mut self) -> Result<String, ParseError> {
+        let mut text = String::new();
+        loop {
+            let c = match self.peek_char() {
+                Some(c) => c,
+                None => return Err(ParseError::Incomplete("EOF while scanning string literal".to_string())),
+            };
+            self.pos += 1;
+            match c {
+                '\'' => return Ok(text),
+                '\\' => {
+                    let escape = match self.peek_char() {
+                        Some(c) => c,
+                        None => return Err(ParseError::Incomplete("EOF while scanning string literal".to_string())),
+                    };
+                    self.pos += 1;
+                    let escaped = match escape {
+                        'n' => Some('\n'),
+                        't' => Some('\t'),
+                        'r' => Some('\r'),
+                        'a' => Some('\x07'),
+                        'b' => Some('\x08'),
+                        'e' | 'E' => Some('\x1b'),
+                        'f' => Some('\x0c'),
+                        'v' => Some('\x0b'),
+                        '\\' | '\'' | '"' | '?' => Some(escape),
+                        'x' => self.read_number(16, 2).and_then(char::from_u32),
+                        'u' => self.read_number(16, 4).and_then(char::from_u32),
+                        'U' => self.read_number(16, 8).and_then(char::from_u32),
+                        '0'..='7' => {
+                            self.pos -= 1;
+                            self.read_number(8, 3).and_then(char::from_u32)
+                        }
+                        _ => None,
+                    };
+                    match escaped {
+                        Some(c) => text.push(c),
+                        None => {
+                            text.push('\\');
+                            text.push(escape);
+                        }
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+    }
+
+    //called just after a `$`, returns None if it doesn't start an expansion
+    fn read_dollar(&mut self) -> Result<Option<WordPart>, ParseError> {
+        match self.peek_char() {
+            Some('(') if self.chars.get(self.pos + 1) == Some(&'(') => {
+                self.pos += 2;
+                let text = self.read_arith_body()?;
+                parse_arith_text(&text, self.aliases).map(|word| Some(WordPart::Arith(word)))
+            }
+            Some('(') => {
+                self.pos += 1;
+                let text = self.read_balanced()?;
+                self.parse_substitution(&text).map(|list| Some(WordPart::CommandSub(list)))
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.read_braced_param().map(Some)
+            }
+            //special parameters and positional parameters past $9 need braces
+            Some(c) if matches!(c, '?' | '#' | '@' | '*' | '!') || c.is_ascii_digit() => {
+                self.pos += 1;
+                Ok(Some(WordPart::Param(c.to_string())))
+            }
+            Some(c) if is_name_char(c) => {
+                let mut name = String::new();
+                while let Some(c) = self.peek_char() {
+                    if !is_name_char(c) { break; }
+                    name.push(c);
+                    self.pos += 1;
+                }
+                Ok(Some(WordPart::Param(name)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    //the name at the start of a `${...}`, special parameters are a single character
+    fn read_param_name(&mut self) -> String {
+        match self.peek_char() {
+            Some(c) if matches!(c, '?' | '#' | '@' | '*' | '!') => {
+                self.pos += 1;
+                c.to_string()
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let digits: String = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                self.pos += digits.len();
+                digits
+            }
+            _ => {
+                let mut name: String = self.chars[self.pos..].iter().take_while(|c| is_name_char(**c)).collect();
+                self.pos += name.chars().count();
+                //an array subscript is kept as part of the name, it is expanded along with it
+                if !name.is_empty() && self.peek_char() == Some('[') {
+                    let mut depth = 0;
+                    let end = self.chars[self.pos..].iter().position(|c| {
+                        match c {
+                            '[' => depth += 1,
+                            ']' => depth -= 1,
+                            _ => (),
+                        }
+                        depth == 0
+                    });
+                    if let Some(end) = end {
+                        name.extend(&self.chars[self.pos..=self.pos + end]);
+                        self.pos += end + 1;
+                    }
+                }
+                name
+            }
+        }
+    }
+
+    //everything after the `${`
+    fn read_braced_param(&mut self) -> Result<WordPart, ParseError> {
+        let start = self.pos;
+        //`${#name}` is a length, but `${#}` is just $#
+        if self.peek_char() == Some('#') && !matches!(self.chars.get(self.pos + 1), Some('}') | None) {
+            self.pos += 1;
+            let name = self.read_param_name();
+            if !name.is_empty() && self.peek_char() == Some('}') {
+                self.pos += 1;
+                return Ok(WordPart::ParamExp(name, Box::new(ParamOp::Length)));
+            }
+            self.pos = start;
+        }
+        if self.peek_char() == Some('!') && self.chars.get(self.pos + 1).is_some_and(|c| is_name_char(*c)) {
+            self.pos += 1;
+            let name = self.read_param_name();
+            if (name.ends_with("[@]") || name.ends_with("[*]")) && self.peek_char() == Some('}') {
+                self.pos += 1;
+                return Ok(WordPart::ParamExp(name, Box::new(ParamOp::Keys)));
+            }
+            return Err(self.bad_substitution(start));
+        }
+        let name = self.read_param_name();
+        let c = match self.peek_char() {
+            Some(c) if !name.is_empty() => c,
+            _ => return Err(self.bad_substitution(start)),
+        };
+        self.pos += 1;
+        let op = match c {
+            '}' => return Ok(WordPart::Param(name)),
+            ':' if matches!(self.peek_char(), Some('-') | Some('=') | Some('?') | Some('+')) => {
+                let c = self.peek_char().unwrap();
+                self.pos += 1;
+                self.read_test_op(c, true)?
+            }
+            ':' => {
+                let (offset, end) = self.read_param_word(&[':', '}'])?;
+                let length = if end == ':' { Some(self.read_param_word(&['}'])?.0) } else { None };
+                ParamOp::Substring(offset, length)
+            }
+            '-' | '=' | '?' | '+' => self.read_test_op(c, false)?,
+            '#' | '%' => {
+                let longest = self.peek_char() == Some(c);
+                if longest {
+                    self.pos += 1;
+                }
+                let pattern = self.read_param_word(&['}'])?.0;
+                if c == '#' {
+                    ParamOp::RemovePrefix(longest, pattern)
+                } else {
+                    ParamOp::RemoveSuffix(longest, pattern)
+                }
+            }
+            '/' => {
+                let mode = match self.peek_char() {
+                    Some('/') => ReplaceMode::All,
+                    Some('#') => ReplaceMode::Prefix,
+                    Some('%') => ReplaceMode::Suffix,
+                    _ => ReplaceMode::First,
+                };
+                if mode != ReplaceMode::First {
+                    self.pos += 1;
+                }
+                let (pattern, end) = self.read_param_word(&['/', '}'])?;
+                let replacement = if end == '/' { self.read_param_word(&['}'])?.0 } else { Word { parts: Vec::new() } };
+                ParamOp::Replace { mode, pattern, replacement }
+            }
+            '^' | ',' => {
+                let all = self.peek_char() == Some(c);
+                if all {
+                    self.pos += 1;
+                }
+                if self.peek_char() != Some('}') {
+                    return Err(self.bad_substitution(start));
+                }
+                self.pos += 1;
+                ParamOp::Case { upper: c == '^', all }
+            }
+            _ => return Err(self.bad_substitution(start)),
+        };
+        Ok(WordPart::ParamExp(name, Box::new(op)))
+    }
+
+    fn read_test_op(&mut self, c: char, colon: bool) -> Result<ParamOp, ParseError> {
+        let word = self.read_param_word(&['}'])?.0;
+        Ok(match c {
+            '-' => ParamOp::Default(colon, word),
+            '=' => ParamOp::Assign(colon, word),
+            '?' => ParamOp::Error(colon, word),
+            _ => ParamOp::Alternative(colon, word),
+        })
+    }
+
+    //skips to the closing `}` of a `${` that started at start, for the error message
+    fn bad_substitution(&mut self, start: usize) -> ParseError {
+        let end = self.chars[start..].iter().position(|c| *c == '}');
+        match end {
+            Some(end) => {
+                let text: String = self.chars[start..start + end].iter().collect();
+                self.pos = start + end + 1;
+                ParseError::Syntax(format!("${{{}}}: bad substitution", text))
+            }
+            None => ParseError::Incomplete("EOF while looking for matching `}'".to_string()),
+        }
+    }
+
+    //cuts the body of a here-document out of the lines after the current one, so that
+    //several on one line take their bodies in turn
+    pub fn read_heredoc(&mut self, delimiter: &str, strip_tabs: bool) -> Result<String, ParseError> {
+        let incomplete = || ParseError::Incomplete(format!("here-document delimited by end-of-file (wanted `{}')", delimiter));
+        let start = match self.chars[self.pos..].iter().position(|c| *c == '\n') {
+            Some(i) => self.pos + i + 1,
+            None => return Err(incomplete()),
+        };
+        let mut body = String::new();
+        let mut end = start;
+        loop {
+            if end >= self.chars.len() {
+                return Err(incomplete());
+            }
+            let line_end = self.chars[end..].iter().position(|c| *c == '\n').map_or(self.chars.len(), |i| end + i);
+            let line: String = self.chars[end..line_end].iter().collect();
+            end = (line_end + 1).min(self.chars.len());
+            let line = if strip_tabs { line.trim_start_matches('\t') } else { &line };
+            if line == delimiter {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        self.chars.drain(start..end);
+        self.inserted.drain(start..end);
+        Ok(body)
+    }
+
+    //the right side of `=~` in `[[`, which goes on up to a blank outside of quotes and
+    //parentheses so that `(`, `)` and `|` can be used in the regex
+    pub fn read_regex(&mut self) -> Result<Word, ParseError> {
+        while let Some(' ') | Some('\t') = self.peek_char() {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        let mut depth = 0;
+        let mut quote: Option<char> = None;
+        while let Some(c) = self.peek_char() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some('"'), '\\') => self.pos += 1,
+                (Some(_), _) => (),
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (None, '\\') => self.pos += 1,
+                (None, '(') => depth += 1,
+                (None, ')') if depth > 0 => depth -= 1,
+                (None, ' ') | (None, '\t') | (None, '\n') | (None, ';') | (None, '&') | (None, ')') if depth == 0 => break,
+                (None, '|') if depth == 0 && self.chars.get(self.pos + 1) == Some(&'|') => break,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+        if quote.is_some() {
+            return Err(ParseError::Incomplete("EOF while scanning string literal".to_string()));
+        }
+        let text: String = self.chars[start..self.pos.min(self.chars.len())].iter().collect();
+        parse_word_text(&text, self.aliases)
+    }
+
+    //the word operand of a `${...}` up to one of the stop characters, which is consumed and
+    //returned, braces nest and quotes and expansions work as they do in a word
+    fn read_param_word(&mut self, stop: &[char]) -> Result<(Word, char), ParseError> {
+        let mut parts: Vec<WordPart> = Vec::new();
+        let mut lit = String::new();
+        let mut depth = 0;
+        loop {
+            let c = match self.peek_char() {
+                Some(c) => c,
+                None => return Err(ParseError::Incomplete("EOF while looking for matching `}'".to_string())),
+            };
+            if depth == 0 && stop.contains(&c) {
+                self.pos += 1;
+                push_literal(&mut parts, &mut lit);
+                return Ok((Word { parts }, c));
+            }
+            match c {
+                '"' => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    let inner = self.read_double_quoted()?;
+                    parts.push(WordPart::DoubleQuoted(inner));
+                }
+                '\'' => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    let text = self.read_single_quoted()?;
+                    parts.push(WordPart::Quoted(text));
+                }
+                '\\' => {
+                    if self.is_line_continuation() {
+                        self.skip_line_continuation()?;
+                        continue;
+                    }
+                    self.pos += 1;
+                    if let Some(c) = self.peek_char() {
+                        push_literal(&mut parts, &mut lit);
+                        parts.push(WordPart::Quoted(c.to_string()));
+                        self.pos += 1;
+                    }
+                }
+                '$' => {
+                    self.pos += 1;
+                    match self.read_dollar()? {
+                        Some(part) => {
+                            push_literal(&mut parts, &mut lit);
+                            parts.push(part);
+                        }
+                        None => lit.push('$'),
+                    }
+                }
+                '`' => {
+                    push_literal(&mut parts, &mut lit);
+                    self.pos += 1;
+                    parts.push(self.read_backquoted(false)?);
+                }
+                c => {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    lit.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    //the commands of a substitution, whose end has already been found
+    fn parse_substitution(&self, text: &str) -> Result<List, ParseError> {
+        parser::parse(text, self.aliases).map_err(|e| match e {
+            ParseError::Incomplete(msg) => ParseError::Syntax(msg),
+            e => e,
+        })
+    }
+
+    //called just after a backquote, a backslash only escapes `$`, `` ` ``, `\` and in double
+    //quotes `"` before the text is parsed as a command substitution
+    fn read_backquoted(&mut self, in_quotes: bool) -> Result<WordPart, ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.peek_char() {
+                None => return Err(ParseError::Incomplete("EOF while looking for matching ``'".to_string())),
+                Some('`') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('\\') => {
+                    match self.chars.get(self.pos + 1) {
+                        Some(&c) if matches!(c, '$' | '`' | '\\') || c == '"' && in_quotes => {
+                            text.push(c);
+                            self.pos += 2;
+                        }
+                        _ => {
+                            text.push('\\');
+                            self.pos += 1;
+                        }
+                    }
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        self.parse_substitution(&text).map(WordPart::CommandSub)
+    }
+
+    //reads up to the `)` matching an already consumed `(`, skipping over strings and comments,
+    //the `)` after a case pattern doesn't end it
+    fn read_balanced(&mut self) -> Result<String, ParseError> {
+        let mut text = String::new();
+        let mut depth = 1;
+        let mut quote: Option<char> = None;
+        //the unquoted word being read, whether it follows `case` and the depths of the open cases
+        let mut word = String::new();
+        let mut after_case = false;
+        let mut cases = Vec::new();
+        loop {
+            let c = match self.peek_char() {
+                Some(c) => c,
+                None => return Err(ParseError::Incomplete("EOF while looking for matching `)'".to_string())),
+            };
+            self.pos += 1;
+            if quote.is_none() && (is_metachar(c) || matches!(c, '"' | '\'' | '`')) {
+                match std::mem::take(&mut word).as_str() {
+                    "case" => after_case = true,
+                    "in" if after_case => {
+                        after_case = false;
+                        cases.push(depth);
+                    }
+                    "esac" => {
+                        cases.pop();
+                    }
+                    _ => (),
+                }
+                if !matches!(c, ' ' | '\t') {
+                    after_case = false;
+                }
+            }
+            match (quote, c) {
+                (Some('\''), '\'') => quote = None,
+                (Some('\''), _) => (),
+                (_, '\\') => {
+                    if let Some(next) = self.peek_char() {
+                        text.push(c);
+                        text.push(next);
+                        self.pos += 1;
+                        continue;
+                    }
+                }
+                (Some(q), _) if q == c => quote = None,
+                (Some(_), _) => (),
+                (None, '"') | (None, '\'') | (None, '`') => quote = Some(c),
+                (None, '#') if word.is_empty() && !text.ends_with(|c: char| !is_metachar(c)) => {
+                    while let Some(c) = self.peek_char() {
+                        if c == '\n' { break; }
+                        self.pos += 1;
+                    }
+                    continue;
+                }
+                (None, '(') => depth += 1,
+                (None, ')') if cases.last() == Some(&depth) => (),
+                (None, ')') => {
+                    depth -= 1;
+                    if depth == 0 { break; }
+                }
+                (None, c) if !is_metachar(c) => word.push(c),
+                _ => (),
+            }
+            text.push(c);
+        }
+        Ok(text)
+    }
+}
+
+//the body of a here-document whose delimiter isn't quoted, only expansions and
+//backslashes are special in it
+pub fn parse_heredoc_text(text: &str, aliases: &HashMap<String, String>) -> Result<Word, ParseError> {
+    let mut lexer = Lexer::new(text, aliases);
+    let parts = lexer.read_quoted_text(None)?;
+    Ok(Word { parts: vec![WordPart::DoubleQuoted(parts)] })
+}
+
+//text as a single word that ends at the end of the text, for array subscripts and the like
+//where quotes are removed but nothing is split
+pub fn parse_word_text(text: &str, aliases: &HashMap<String, String>) -> Result<Word, ParseError> {
+    let text = format!("{}\n", text);
+    let mut lexer = Lexer::new(&text, aliases);
+    Ok(lexer.read_param_word(&['\n'])?.0)
+}
+
+//arithmetic text is expanded as if it was in double quotes, without field splitting
+pub fn parse_arith_text(text: &str, aliases: &HashMap<String, String>) -> Result<Word, ParseError> {
+    let mut lexer = Lexer::new(text, aliases);
+    let parts = lexer.read_quoted_text(None)?;
+    Ok(Word { parts: vec![WordPart::DoubleQuoted(parts)] })
+}
@@ -1,16 +1,35 @@
 use std::io::Write;
-use std::io::BufRead;
 use std::env;
 extern crate dirs;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use termion;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
-use termion::event::Event;
-use termion::event::Key;
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+
+mod lexer;
+mod parser;
+mod expand;
+mod exec;
+mod arith;
+mod sys;
+mod jobs;
+mod trap;
+mod glob;
+mod array;
+mod cond;
+mod vi;
+mod editor;
+
+use array::Value;
+use exec::Flow;
+use editor::ReadResult;
+
+//names accepted by `set -o`
+const OPTIONS: &[&str] = &["pipefail", "noglob", "nullglob", "failglob", "dotglob", "nocaseglob", "emacs", "vi"];
 
 fn expand_tilde<P: AsRef<Path>>(path_user_input: &P) -> Option<PathBuf> {
     let p = path_user_input.as_ref();
@@ -32,6 +51,19 @@ fn expand_tilde<P: AsRef<Path>>(path_user_input: &P) -> Option<PathBuf> {
     })
 }
 
+//what builtins print goes through here, so that a failed write (to a full disk, say) is reported
+//rather than taking the shell down, giving the builtin's status
+fn write_out(builtin: &str, text: &str) -> i32 {
+    let mut out = std::io::stdout().lock();
+    match out.write_all(text.as_bytes()).and_then(|_| out.flush()) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("josh: {}: write error: {}", builtin, sys::error_message(&e));
+            1
+        }
+    }
+}
+
 fn get_path_extensions<T>(init_path: String, filter_func: T) -> (Vec<String>, Vec<String>) where T: Fn(PathBuf) -> bool {
     let mut valid_paths: Vec<String> = Vec::new();
     let mut short_paths: Vec<String> = Vec::new();
@@ -72,8 +104,8 @@ fn get_path_extensions<T>(init_path: String, filter_func: T) -> (Vec<String>, Ve
 }
 
 fn get_tab_complete(input: &str) -> (Vec<String>, Vec<String>) {
-    let argv: Vec<&str> = input.split_whitespace().into_iter().collect();
-    if argv.len() == 1 && !input.ends_with(" ") {
+    let argv: Vec<&str> = input.split_whitespace().collect();
+    if argv.len() == 1 && !input.ends_with(' ') {
         //complete command
         let mut valid_paths: Vec<String> = Vec::new();
         let mut short_paths: Vec<String> = Vec::new();
@@ -89,12 +121,12 @@ fn get_tab_complete(input: &str) -> (Vec<String>, Vec<String>) {
             }
         }
         //none were found
-        if valid_paths.len() == 0 {
+        if valid_paths.is_empty() {
             get_path_extensions(argv[0].to_string(), |path_buf| path_buf.metadata().unwrap().permissions().mode() & 0b001001001 > 0)
         } else {
             (valid_paths, short_paths)
         }
-    } else if input.ends_with(" ") {
+    } else if input.ends_with(' ') {
         //new arg
         let mut res: Vec<String> = Vec::new();
         for path in std::fs::read_dir("./").unwrap() {
@@ -108,7 +140,7 @@ fn get_tab_complete(input: &str) -> (Vec<String>, Vec<String>) {
         (res.clone(), res)
     } else {
         //complete current arg
-        if argv.len() == 0 {
+        if argv.is_empty() {
             return (Vec::new(), Vec::new());
         }
         get_path_extensions(argv[argv.len() - 1].to_string(), |_| true)
@@ -116,18 +148,96 @@ fn get_tab_complete(input: &str) -> (Vec<String>, Vec<String>) {
     
 }
 
+//a history entry as a line of the history file, the lines of a multi-line command end in
+//a backslash and the entry's own backslashes are doubled so they can't be taken for one
+fn encode_history(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\\n")
+}
+
+fn decode_history(content: &str) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    let mut entry = String::new();
+    for line in content.lines() {
+        let trailing = line.chars().rev().take_while(|c| *c == '\\').count();
+        let continued = trailing % 2 == 1;
+        let line = if continued { &line[..line.len() - 1] } else { line };
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            entry.push(c);
+            if c == '\\' && chars.as_str().starts_with('\\') {
+                chars.next();
+            }
+        }
+        if continued {
+            entry.push('\n');
+        } else if !entry.is_empty() {
+            entries.push(std::mem::take(&mut entry));
+        }
+    }
+    entries
+}
+
+#[derive(Clone)]
+struct Var {
+    value: Value,
+    //exported variables are passed on in the environment of spawned commands
+    exported: bool,
+}
+
 struct Shell {
     w_dir: PathBuf,
     rc_path: PathBuf,
     hist_path: PathBuf,
-    vars: HashMap<String, String>,
+    vars: HashMap<String, Var>,
     aliases: HashMap<String, String>,
+    functions: HashMap<String, Rc<parser::Command>>,
+    //$1 and onwards, $0 is arg0
+    positional: Vec<String>,
+    arg0: String,
+    //one frame per running function, holding what each `local` shadowed
+    locals: Vec<Vec<(String, Option<Var>)>>,
+    options: HashSet<String>,
+    last_status: i32,
+    exiting: bool,
+    flow: Flow,
+    //how many loops the command being run is nested in, for break and continue
+    loop_depth: u32,
+    jobs: Vec<jobs::Job>,
+    //$!
+    last_bg_pid: Option<libc::pid_t>,
+    //set for an interactive shell, which puts each job in its own process group
+    job_control: bool,
+    //the terminal modes to go back to when a job stops
+    shell_termios: Option<libc::termios>,
+    //`exit` warns once about stopped jobs before really exiting
+    exit_warned: bool,
+    //trap actions by signal name or EXIT, ERR and DEBUG, empty if the signal is ignored
+    traps: HashMap<String, String>,
+    //stops traps from triggering themselves
+    in_trap: bool,
+    //set by ctrl-c in an interactive shell, abandons what is running and goes back to the prompt
+    interrupted: bool,
+    //how many `if`, `while` or `until` conditions are being run, ERR doesn't trigger in them
+    condition_depth: u32,
+    //set when an expansion fails, the command it was for is not run
+    expand_failed: bool,
+    //how many variables deep the arithmetic being evaluated is
+    arith_depth: u32,
+    //the status of the last command substitution in the command being run
+    sub_status: Option<i32>,
+    //the pipe fds and pids of the process substitutions of the commands being run
+    proc_subs: Vec<(std::os::unix::io::RawFd, libc::pid_t)>,
+    //the line editor at the prompt, with the keys `bind` has set up
+    editor: editor::LineEditor,
 }
 
 impl Shell {
     fn new() -> Self {
-        let mut vars: HashMap<String, String> = HashMap::new();
-        vars.insert("PS1".to_string(), r#"> "#.to_string());
+        let mut vars: HashMap<String, Var> = env::vars()
+            .map(|(name, value)| (name, Var { value: Value::Scalar(value), exported: true }))
+            .collect();
+        vars.entry("PS1".to_string()).or_insert(Var { value: Value::Scalar(r#"> "#.to_string()), exported: false });
+        vars.entry("PS2".to_string()).or_insert(Var { value: Value::Scalar(r#"> "#.to_string()), exported: false });
         let w_dir = std::env::current_dir().unwrap();
         let mut rc_path = dirs::home_dir().unwrap();
         let mut hist_path = dirs::home_dir().unwrap();
@@ -136,445 +246,517 @@ impl Shell {
         Shell {
             w_dir, rc_path, hist_path,
             vars, aliases: HashMap::new(),
+            functions: HashMap::new(),
+            positional: Vec::new(),
+            arg0: "josh".to_string(),
+            locals: Vec::new(),
+            options: HashSet::from(["emacs".to_string()]),
+            last_status: 0,
+            exiting: false,
+            flow: Flow::Normal,
+            loop_depth: 0,
+            jobs: Vec::new(),
+            last_bg_pid: None,
+            job_control: false,
+            shell_termios: None,
+            exit_warned: false,
+            traps: HashMap::new(),
+            in_trap: false,
+            interrupted: false,
+            condition_depth: 0,
+            expand_failed: false,
+            arith_depth: 0,
+            sub_status: None,
+            proc_subs: Vec::new(),
+            editor: editor::LineEditor::new(get_tab_complete),
+        }
+    }
+
+    //takes over the terminal, waiting first if josh was started in the background
+    fn init_job_control(&mut self) {
+        loop {
+            let pgrp = sys::getpgrp();
+            if sys::tcgetpgrp(0) == pgrp {
+                break;
+            }
+            sys::kill(-pgrp, libc::SIGTTIN).ok();
         }
+        self.init_signals();
+        let pid = sys::getpid();
+        sys::setpgid(pid, pid);
+        sys::tcsetpgrp(0, pid);
+        self.shell_termios = sys::get_termios(0);
+        self.job_control = true;
     }
 
+    //entries spanning several lines are written with a backslash ending all but their last line
     fn append_history(&self, item: &str) {
-        if item == "" { return; }
+        if item.is_empty() { return; }
         let mut file = std::fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.hist_path)
             .unwrap();
-        writeln!(file, "{}", item).unwrap();
+        writeln!(file, "{}", encode_history(item)).unwrap();
     }
 
-    fn read_history(&self, line_num: usize) -> String {
-        if let Ok(file) = std::fs::File::open(&self.hist_path) {
-            let content = std::io::BufReader::new(&file);
-            let mut lines = content.lines();
-            return lines.nth(line_num).unwrap().unwrap();
-        } else {
-            return "".to_string();
+    fn history_entries(&self) -> Vec<String> {
+        decode_history(&std::fs::read_to_string(&self.hist_path).unwrap_or_default())
+    }
+
+    //PS1, or PS2 for the lines continuing a command
+    fn get_prompt(&self, name: &str) -> String {
+        let fmt_string = self.vars.get(name).and_then(|var| var.value.scalar()).unwrap_or_default();
+        fmt_string
+            .replace("\\w", &std::env::current_dir().unwrap().to_str().unwrap().replace(dirs::home_dir().unwrap().to_str().unwrap(), "~"))
+            .replace("\\h", &whoami::hostname())
+            .replace("\\u", &whoami::username())
+    }
+
+    //setting an array without a subscript sets its element 0
+    fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(Var { value: Value::Indexed(map), .. }) => {
+                map.insert(0, value);
+            }
+            Some(Var { value: Value::Assoc(map), .. }) => {
+                map.insert("0".to_string(), value);
+            }
+            Some(var) => var.value = Value::Scalar(value),
+            None => {
+                self.vars.insert(name.to_string(), Var { value: Value::Scalar(value), exported: false });
+            }
         }
     }
 
-    fn get_hist_len(&self) -> usize {
-        let file = std::fs::File::open(&self.hist_path);
-        if let Ok(file) = file {
-            let reader = std::io::BufReader::new(file);
-            let mut count = 0;
-            for line in reader.lines() { 
-                if line.unwrap() != "" { count += 1; }
+    //arrays can't be put in the environment
+    fn exported_vars(&self) -> Vec<(&String, &String)> {
+        self.vars.iter()
+            .filter_map(|(name, var)| match &var.value {
+                Value::Scalar(value) if var.exported => Some((name, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn export_builtin(&mut self, argv: &[String]) -> i32 {
+        if argv.is_empty() || argv == ["-p"] {
+            let mut names: Vec<&String> = self.vars.iter().filter(|(_, var)| var.exported).map(|(name, _)| name).collect();
+            names.sort();
+            let text: String = names.iter().map(|name| {
+                let value = self.vars[*name].value.scalar().unwrap_or_default().replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$");
+                format!("export {}=\"{}\"\n", name, value)
+            }).collect();
+            return write_out("export", &text);
+        }
+        let mut status = 0;
+        let mut exported = true;
+        for arg in argv {
+            if arg == "-n" {
+                exported = false;
+                continue;
+            }
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !parser::is_name(name) {
+                eprintln!("josh: export: `{}': not a valid identifier", arg);
+                status = 1;
+                continue;
+            }
+            let var = self.vars.entry(name.to_string()).or_insert(Var { value: Value::Scalar(String::new()), exported });
+            var.exported = exported;
+            if let Some(value) = value {
+                self.set_var(name, value);
             }
-            count
-        } else {
-            0
         }
+        status
     }
-    
-    fn get_ps1(&self) -> String {
-        let fmt_string = self.vars.get("PS1").unwrap();
-        format!("{}", fmt_string
-            .replace("\\w", &std::env::current_dir().unwrap().to_str().unwrap().replace(dirs::home_dir().unwrap().to_str().unwrap(), "~"))
-            .replace("\\h", &whoami::hostname())
-            .replace("\\u", &whoami::username())
-        )
+
+    //a fresh variable for the function being run, the old one comes back when it returns
+    fn make_local(&mut self, name: &str) {
+        let frame = self.locals.last_mut().unwrap();
+        if !frame.iter().any(|(n, _)| n == name) {
+            frame.push((name.to_string(), self.vars.get(name).cloned()));
+        }
+        self.vars.insert(name.to_string(), Var { value: Value::Scalar(String::new()), exported: false });
     }
 
-    fn execute_command_get_output(&mut self, command: &str, argv: &[String]) -> String {
-        match command {
-            "alias" | "cd" => {
-                "".to_string();
-            },
+    fn local_builtin(&mut self, argv: &[String]) -> i32 {
+        if self.locals.is_empty() {
+            eprintln!("josh: local: can only be used in a function");
+            return 1;
+        }
+        self.declare_builtin("local", argv)
+    }
 
-            command => {
-                let res = std::process::Command::new(command)
-                    .args(argv)
-                    .stdout(std::process::Stdio::piped())
-                    .output();
-                match res {
-                    Ok(child) => {
-                        return String::from_utf8_lossy(&child.stdout).to_string();
-                    }
-                    Err(_) => {
-                        eprintln!("josh: {}: command not found", command);
-                    }
-                }
-            },
+    fn option(&self, name: &str) -> bool {
+        self.options.contains(name)
+    }
+
+    fn set_builtin(&mut self, argv: &[String]) -> i32 {
+        if argv.len() == 1 && argv[0] == "-o" {
+            let text: String = OPTIONS.iter()
+                .map(|name| format!("{:15}{}\n", name, if self.option(name) { "on" } else { "off" }))
+                .collect();
+            return write_out("set", &text);
+        }
+        if argv.len() != 2 || (argv[0] != "-o" && argv[0] != "+o") {
+            eprintln!("josh: set: usage: set [-o|+o] option");
+            return 2;
+        }
+        if !OPTIONS.contains(&argv[1].as_str()) {
+            eprintln!("josh: set: {}: invalid option name", argv[1]);
+            return 1;
+        }
+        if argv[0] == "-o" {
+            //the editing modes turn each other off
+            match argv[1].as_str() {
+                "vi" => self.options.remove("emacs"),
+                "emacs" => self.options.remove("vi"),
+                _ => false,
+            };
+            self.options.insert(argv[1].clone());
+        } else {
+            self.options.remove(&argv[1]);
         }
-        "".to_string()
+        0
     }
 
-    fn execute_command(&mut self, command: &str, argv: &[String]) -> bool {
+    fn execute_command(&mut self, command: &str, argv: &[String]) -> i32 {
+        if let Some(body) = self.functions.get(command) {
+            return self.call_function(body.clone(), argv);
+        }
         match command {
             "cd" => {
                 if argv.len() == 1 {
                     self.w_dir = PathBuf::from(argv[0].clone());
-                } else if argv.len() == 0 {
+                } else if argv.is_empty() {
                     self.w_dir = PathBuf::from("~");
                 } else {
-                    println!("josh: cd: too many arguments");
-                    return true;
+                    eprintln!("josh: cd: too many arguments");
+                    return 1;
                 }
                 
-                if let Err(e) = env::set_current_dir(&expand_tilde(&self.w_dir).unwrap()) {
+                if let Err(e) = env::set_current_dir(expand_tilde(&self.w_dir).unwrap()) {
                     match e.raw_os_error() {
                         Some(2) => {
-                            println!("josh: cd: {}: No such file or directory", self.w_dir.to_str().unwrap());
+                            eprintln!("josh: cd: {}: No such file or directory", self.w_dir.to_str().unwrap());
                         }
                         Some(_) => {
                             eprintln!("josh: cd: other error: {}", e);
                         }
                         None => ()
                     }
+                    return 1;
                 }
+                let pwd = env::current_dir().unwrap().to_str().unwrap().to_string();
+                self.set_var("PWD", pwd);
             },
             "alias" => {
-                if argv.len() > 2 {
+                if argv.is_empty() {
+                    let mut names: Vec<&String> = self.aliases.keys().collect();
+                    names.sort();
+                    let text: String = names.iter().map(|name| format!("alias {} \"{}\"\n", name, self.aliases[*name])).collect();
+                    return write_out("alias", &text);
+                } else if argv.len() > 2 {
                     eprintln!("josh: alias: too many arguments");
+                    return 1;
                 } else if argv.len() < 2 {
                     eprintln!("josh: alias: too few arguments");
+                    return 1;
                 } else {
                     self.aliases.insert(argv[0].to_owned(), argv[1].to_owned());
                 }
             },
 
-            "exit" => return false,
+            "set" => return self.set_builtin(argv),
+            "bind" => return self.bind_builtin(argv),
 
-            command => {
-                let actual_command: &str;
-                let mut actual_argv: Vec<&String> = argv.iter().collect();
-                let alias_argv: Vec<String>;
-                if self.aliases.contains_key(command) {
-                    alias_argv = self.parse_argv(self.aliases[command].to_owned()).unwrap(); //TODO unwrap bad here
-                    actual_command = &alias_argv[0];
-                    actual_argv.extend(&alias_argv[1..]);
-                } else {
-                    actual_command = command;
-                }
-                let res = std::process::Command::new(actual_command).args(actual_argv).spawn();
-                match res {
-                    Ok(mut child) => {
-                        child.wait().unwrap();
-                    }
-                    Err(e) => {
-                        println!("josh: {}: command not found {:?}", command, e);
-                    }
+            "export" => return self.export_builtin(argv),
+
+            "break" | "continue" => {
+                if self.loop_depth == 0 {
+                    eprintln!("josh: {}: only meaningful in a `for', `while', or `until' loop", command);
+                    return 0;
                 }
+                let n = match argv.first() {
+                    None => 1,
+                    Some(arg) => match arg.parse::<u32>() {
+                        Ok(n) if n > 0 => n.min(self.loop_depth),
+                        _ => {
+                            eprintln!("josh: {}: {}: loop count out of range", command, arg);
+                            return 1;
+                        }
+                    },
+                };
+                self.flow = if command == "break" { Flow::Break(n) } else { Flow::Continue(n) };
             },
-        }
-        true
-    }
 
-    fn exec_rc(&mut self) {
-        let file = std::fs::File::open(&self.rc_path).unwrap();
-        let reader = std::io::BufReader::new(file);
-    
-        for line in reader.lines() {
-            let mut input = line.unwrap();
-            if input == "" {
-                continue;
-            }
-            if input.ends_with('\n') {
-                input.pop();
-                if input.ends_with('\r') {
-                    input.pop();
+            "return" => {
+                if self.locals.is_empty() {
+                    eprintln!("josh: return: can only `return' from a function");
+                    return 1;
                 }
-            }
+                let status = match argv.first() {
+                    None => self.last_status,
+                    Some(arg) => match arg.parse::<i32>() {
+                        Ok(status) => status & 0xff,
+                        Err(_) => {
+                            eprintln!("josh: return: {}: numeric argument required", arg);
+                            2
+                        }
+                    },
+                };
+                self.flow = Flow::Return;
+                return status;
+            },
+
+            "local" => return self.local_builtin(argv),
+
+            "declare" => return self.declare_builtin(command, argv),
 
-            let argv = self.parse_argv(input);
-            match argv {
-                Some(argv) => {
-                    if argv.len() == 0 { continue; }
-                    if !self.execute_command(&argv[0], &argv[1..]) {
-                        break;
+            "test" | "[" => return self.test_builtin(command, argv),
+
+            "shift" => {
+                let n = match argv.first().map(|arg| arg.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => {
+                        eprintln!("josh: shift: {}: numeric argument required", argv[0]);
+                        return 1;
                     }
+                };
+                if n > self.positional.len() {
+                    eprintln!("josh: shift: shift count out of range");
+                    return 1;
                 }
-                None => {
-                }
-            }
-        }
-    }
-    
-    fn run(&mut self) {
-        self.exec_rc();
-        loop {
-            print!("{}", self.get_ps1());
-            std::io::stdout().flush().unwrap();
+                self.positional.drain(..n);
+            },
 
-            let mut stdout = std::io::stdout().into_raw_mode().unwrap();
-            
-            let mut input = String::new();
-            let mut inp_buffer = String::new();
-            let mut inp_pos: usize = 0;
-            
-            let mut hist_len = self.get_hist_len();
-            let mut hist_pos: usize = hist_len;
-
-            for event in std::io::stdin().events() {
-                let nhist_len = self.get_hist_len();
-                if hist_pos == hist_len && hist_len != nhist_len {
-                    hist_pos = nhist_len;
-                }
-                hist_len = nhist_len;
-                match event.unwrap() {
-                    Event::Key(Key::Ctrl('d')) => {
-                        println!("\r");
-                        return;
-                    }
-                    Event::Key(Key::Ctrl('c')) => {
-                        print!("^C\r\n");
-                        input = "\n".to_string();
-                        break;
-                    }
+            "jobs" => return self.jobs_builtin(argv),
 
-                    Event::Key(Key::Up) => {
-                        if hist_pos > 0 {
-                            hist_pos -= 1;
-                            input = self.read_history(hist_pos);
-                            inp_pos = input.chars().count();
-                        }
-                    }
+            "fg" | "bg" => return self.resume_builtin(command, argv),
 
-                    Event::Key(Key::Left)  => if inp_pos > 0 { inp_pos -= 1 },
-                    Event::Key(Key::Right) => if inp_pos < input.chars().count() { inp_pos += 1 },
-                    
-                    Event::Key(Key::Down) => {
-                        if hist_pos + 1 < hist_len {
-                            hist_pos += 1;
-                            input = self.read_history(hist_pos);
-                            inp_pos = input.chars().count();
-                        } else {
-                            hist_pos = hist_len;
-                            input = inp_buffer.clone();
-                            inp_pos = input.chars().count();
-                        }
-                    }
+            "wait" => return self.wait_builtin(argv),
 
-                    Event::Key(Key::Char('\t')) => {
-                        let results = get_tab_complete(&input);
-                        if results.0.len() == 1 {
-                            let mut argv: Vec<&str> = input.split_whitespace().into_iter().collect();
-                            let len = argv.len();
-                            argv[len - 1] = &results.0[0];
-                            input = argv.join(" ");
-                            inp_pos = input.chars().count();
-                        } else if results.0.len() > 1 {
-                            print!("\n"); //dunno why but this makes the prompt print again like bash lol
-                            print!("\r");
-                            for n in results.1 {
-                                print!("{} ", n);
-                            }
-                            print!("\n");
-                        }
-                    }
-                    
-                    Event::Key(Key::Char('\n')) => {
-                        print!("\r\n");
-                        input.push('\n');
-                        break;
-                    }
-                    Event::Key(Key::Backspace) => {
-                        if inp_pos > 0 {
-                            input.remove(inp_pos - 1);
-                            inp_pos -= 1;
+            "kill" => return self.kill_builtin(argv),
+
+            "trap" => return self.trap_builtin(argv),
+
+            //each argument is an expression, the status is that of the last one
+            "let" => {
+                if argv.is_empty() {
+                    eprintln!("josh: let: expression expected");
+                    return 1;
+                }
+                let mut result = 0;
+                for arg in argv {
+                    match self.eval_arith(arg) {
+                        Ok(n) => result = n,
+                        Err(e) => {
+                            eprintln!("josh: let: {}: {}", arg.trim(), e);
+                            return 1;
                         }
-                        inp_buffer = input.clone();
                     }
-                    Event::Key(Key::Char(c)) => {
-                        input.insert(inp_pos, c);
-                        inp_buffer = input.clone();
-                        inp_pos += 1;
-                    }
-                    _ => ()
-                }
-
-                let pos_from_right = (input.chars().count() - inp_pos) as u16;
-                print!("{}\r{}{}{}",
-                    termion::clear::CurrentLine,
-                    self.get_ps1(),
-                    input,
-                    if pos_from_right > 0 { termion::cursor::Left(pos_from_right).to_string() }
-                    else { "".to_string() },
-                );
-                stdout.flush().unwrap();
-            }
-            drop(stdout);
-            if input == "" {
-                println!("\r");
-                return;
-            }
-            if input.ends_with('\n') {
-                input.pop();
-                if input.ends_with('\r') {
-                    input.pop();
                 }
+                return (result == 0) as i32;
             }
 
-            let hlen = self.get_hist_len();
-            if hlen == 0 || self.read_history(hlen - 1) != input {
-                self.append_history(&input);
-            }
+            "exit" => {
+                self.update_jobs();
+                if self.job_control && !self.exit_warned && self.jobs.iter().any(|job| job.stopped) {
+                    eprintln!("There are stopped jobs.");
+                    self.exit_warned = true;
+                    return 1;
+                }
+                self.exiting = true;
+                return match argv.first() {
+                    Some(status) => match status.parse::<i32>() {
+                        Ok(status) => status & 0xff,
+                        Err(_) => {
+                            eprintln!("josh: exit: {}: numeric argument required", status);
+                            2
+                        }
+                    },
+                    None => self.last_status,
+                };
+            },
 
-            let argv = self.parse_argv(input);
-            match argv {
-                Some(argv) => {
-                    if argv.len() == 0 { continue; }
-                    if argv[0] == "exit" { break; }
-                    if !self.execute_command(&argv[0], &argv[1..]) {
-                        break;
-                    }
+            command => {
+                let mut cmd = std::process::Command::new(command);
+                cmd.args(argv).env_clear().envs(self.exported_vars());
+                if self.job_control {
+                    //every foreground command gets a process group of its own to hand the terminal to
+                    cmd.process_group(0);
                 }
-                None => {
+                let ignored = self.ignored_signals();
+                unsafe {
+                    cmd.pre_exec(move || {
+                        sys::reset_signals(&ignored);
+                        Ok(())
+                    });
                 }
-            }
+                match cmd.spawn() {
+                    Ok(child) => {
+                        let pid = child.id() as libc::pid_t;
+                        let text = std::iter::once(command).chain(argv.iter().map(|arg| arg.as_str())).collect::<Vec<&str>>().join(" ");
+                        return self.wait_foreground(jobs::Job::new(pid, vec![pid], &text));
+                    }
+                    Err(_) => {
+                        eprintln!("josh: {}: command not found", command);
+                        return 127;
+                    }
+                }
+            },
         }
+        0
     }
 
-    fn eval_vars(&mut self, data: Vec<String>) -> Vec<String> {
-        let assign_regex = regex::Regex::new(r#"^\w+=.+"#).unwrap();
-        let mut res: Vec<String> = Vec::new();
-        for arg in data {
-            if assign_regex.is_match(&arg) {
-                let (name, value) = &arg.split_once('=').unwrap();
-                self.vars.insert(name.to_string(), value.to_string());
-            } else {
-                res.push(arg);
-            }
+    fn exec_rc(&mut self) {
+        if let Ok(file) = std::fs::File::open(&self.rc_path) {
+            let name = self.rc_path.to_str().unwrap().to_string();
+            self.run_lines(std::io::BufReader::new(file), &name);
         }
-        res
     }
 
-    fn split_with_strings(&mut self, data: Vec<char>) -> std::option::Option<Vec<String>> {
-        let mut res: Vec<String> = Vec::new();
-        let mut pos: usize = 0;
-        res.push("".to_string());
-        while pos < data.len() {
-            match data[pos] {
-                ' ' => {
-                    if res.last().unwrap().starts_with("~") {
-                        let orig = res.last().unwrap().to_owned();
-                        res.pop();
-                        res.push(expand_tilde(&orig).unwrap().to_str().unwrap().to_owned());
-                    }
-                    res.push("".to_string());
-                    while pos < data.len() && data[pos] == ' ' {
-                        pos += 1;
-                    }
-                }
-                '"' /*"*/ => {
-                    pos += 1;
-                    while pos < data.len() && data[pos] != '"' /*"*/ {
-                        res.last_mut().unwrap().push(data[pos]);
-                        pos += 1;
-                    }
-                    if pos == data.len() {
-                        eprintln!("josh: EOF while scanning string literal");
-                        return None;
-                    }
-                    pos += 1;
-                }
-                c => {
-                    res.last_mut().unwrap().push(c);
-                    pos += 1;
-                }
-            }
-        }
-        if res.last().unwrap() == "" {
-            res.pop();
-        } else {
-            if res.last().unwrap().starts_with("~") {
-                let orig = res.last().unwrap().to_owned();
-                res.pop();
-                res.push(expand_tilde(&orig).unwrap().to_str().unwrap().to_owned());
+    //`josh script args...`
+    fn run_script(&mut self, path: &str, args: &[String]) -> i32 {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("josh: {}: {}", path, sys::error_message(&e));
+                return 127;
             }
-        }
-        Some(self.eval_vars(res))
+        };
+        self.arg0 = path.to_string();
+        self.positional = args.to_vec();
+        self.run_lines(std::io::BufReader::new(file), path);
+        self.last_status
     }
     
-    fn parse_argv(&mut self, total: String) -> std::option::Option<Vec<String>> {
-        let mut res: Vec<char> = Vec::new();
-        let mut pos: usize = 0;
-        let data: Vec<char> = total.chars().into_iter().collect();
-        while pos < data.len() {
-            match data[pos] {
-                '$' => {
-                    pos += 1;
-                    if pos == data.len() { continue; }
-                    match data[pos] {
-                        '(' => {
-                            pos += 1;
-                            let mut command = String::new();
-                            let mut opening = 1;
-                            let mut closing = 0;
-                            while pos < data.len() && opening > closing{
-                                if data[pos] == '(' { opening += 1; }
-                                if data[pos] == ')' { closing += 1; }
-                                command.push(data[pos]);
-                                pos += 1;
-                            }
-                            command.pop(); //the closing )
-
-                            let argv = self.parse_argv(command);
-                            match argv {
-                                Some(argv) => {
-                                    if argv.len() == 0 { continue; }
-                                    let output = self.execute_command_get_output(&argv[0], &argv[1..]);
-                                    for c in output.chars() {
-                                        res.push(c);
-                                    }
-                                }
-                                None => ()
-                            }
-                        }
-
-                        '{' => {
-                            pos += 1;
-                            let mut name = String::new();
-                            while pos < data.len() && data[pos] != '}' {
-                                name.push(data[pos]);
-                                pos += 1;
-                            }
-                            if self.vars.contains_key(&name) {
-                                for c in self.vars.get(&name).unwrap().chars() {
-                                    res.push(c);
-                                }
-                            }
-                        }
+    fn run(&mut self) {
+        self.init_job_control();
+        self.exec_rc();
+        let mut pending = String::new();
+        while !self.exiting {
+            self.notify_jobs();
+            self.check_signals();
+            self.interrupted = false;
+            let prompt = self.get_prompt(if pending.is_empty() { "PS1" } else { "PS2" });
+            print!("{}", prompt);
+            std::io::stdout().flush().unwrap();
 
-                        _ => {
-                            let mut name = String::new();
-                            while pos < data.len() && data[pos].is_alphanumeric() {
-                                name.push(data[pos]);
-                                pos += 1;
-                            }
-                            if self.vars.contains_key(&name) {
-                                for c in self.vars.get(&name).unwrap().chars() {
-                                    res.push(c);
-                                }
-                            }
+            let history = self.history_entries();
+            let vi_mode = self.option("vi");
+            let mut stdout = std::io::stdout().into_raw_mode().unwrap();
+            let keys = std::io::stdin().keys().map_while(Result::ok);
+            let result = self.editor.read_line(keys, &mut stdout, &prompt, &history, vi_mode).unwrap();
+            //commands have to run with the terminal as it was, not in raw mode
+            drop(stdout);
+            let input = match result {
+                ReadResult::Line(line) => line,
+                ReadResult::Interrupted => {
+                    sys::record_signal(libc::SIGINT);
+                    pending.clear();
+                    continue;
+                }
+                ReadResult::Eof => {
+                    println!();
+                    return;
+                }
+                //the line edited with vi's `v` is run straight away
+                ReadResult::Edit(line) => {
+                    let editor = ["VISUAL", "EDITOR"].iter()
+                        .find_map(|name| self.vars.get(*name).and_then(|var| var.value.scalar()).filter(|s| !s.is_empty()))
+                        .unwrap_or("vi")
+                        .to_string();
+                    match vi::edit_in_editor(&editor, &line, self.exported_vars()) {
+                        Some(edited) => {
+                            println!("{}", edited);
+                            edited
                         }
+                        None => continue,
                     }
                 }
-                '\n' => pos += 1,
-                c => {
-                    res.push(c);
-                    pos += 1;
+            };
+
+            //a command continued over several lines goes into the history as one entry
+            //once it is complete
+            let entry = format!("{}{}", pending, input);
+            let parsed = self.parse_line(&mut pending, &input);
+            if !matches!(parsed, Ok(None)) {
+                let entries = self.history_entries();
+                if entries.last() != Some(&entry) {
+                    self.append_history(&entry);
                 }
             }
+
+            //the warning about stopped jobs only holds for the command right after it
+            let warned = self.exit_warned;
+            match parsed {
+                Ok(Some(list)) => self.run_list(&list),
+                Ok(None) => (),
+                Err(e) => eprintln!("josh: {}", e),
+            }
+            if warned {
+                self.exit_warned = false;
+            }
         }
-        self.split_with_strings(res)
     }
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() == 2 {
-        if args[1] == "--version" {
-            println!("0.1.5");
-            return;
+    if args.len() == 2 && args[1] == "--version" {
+        println!("0.1.5");
+        return;
+    }
+    let mut shell = Shell::new();
+    let status = if args.len() > 1 && args[1] == "-c" {
+        //`josh -c 'cmd' [arg0 [args...]]`, like sh
+        match args.get(2) {
+            Some(command) => {
+                if let Some(arg0) = args.get(3) {
+                    shell.arg0 = arg0.clone();
+                    shell.positional = args[4..].to_vec();
+                }
+                shell.run_lines(command.as_bytes(), "josh: -c");
+                shell.last_status
+            }
+            None => {
+                eprintln!("josh: -c: option requires an argument");
+                2
+            }
         }
+    } else if args.len() > 1 {
+        shell.run_script(&args[1], &args[2..])
+    } else if !termion::is_tty(&std::io::stdin()) {
+        shell.run_lines(std::io::stdin().lock(), "josh");
+        shell.last_status
+    } else {
+        shell.run();
+        shell.last_status
+    };
+    shell.last_status = status;
+    shell.run_exit_trap();
+    std::process::exit(shell.last_status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_round_trip() {
+        let entries = ["echo a\\", "echo b", "for i in 1 2\ndo echo $i\\\ndone", "echo \\\\ \\n", "x\\\n\\"];
+        let content: String = entries.iter().map(|entry| format!("{}\n", encode_history(entry))).collect();
+        assert_eq!(decode_history(&content), entries);
+    }
+
+    #[test]
+    fn history_continued_lines() {
+        assert_eq!(decode_history("if true\\\nthen echo\\\nfi\necho\n"), ["if true\nthen echo\nfi", "echo"]);
     }
-    Shell::new().run();
 }
@@ -0,0 +1,849 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::cond;
+use crate::lexer::{self, Lexer, Op, ParseError, Token, Word, WordPart};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assign {
+    pub name: String,
+    //the subscript of `name[subscript]=value`
+    pub index: Option<Word>,
+    pub value: Word,
+    //`+=`, which appends to the value or the array
+    pub append: bool,
+    //the elements of `name=(...)`
+    pub array: Option<Vec<Word>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    In,
+    Out,
+    Append,
+    //`<&` and `>&`, the target is a file descriptor or `-`
+    DupIn,
+    DupOut,
+    //`&>` and `&>>`, stdout and stderr both go to the file
+    OutErr,
+    AppendErr,
+    //`<<` and `<<-`, the target is the body
+    HereDoc,
+    //`<<<`
+    HereString,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<i32>,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub assigns: Vec<Assign>,
+    pub words: Vec<Word>,
+    //`name=(...)` arguments of declare and local, assigned once the builtin has made the variables
+    pub declared: Vec<Assign>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompoundCommand {
+    If {
+        //each `if` or `elif` condition with the list it guards
+        branches: Vec<(List, List)>,
+        else_branch: Option<List>,
+    },
+    Loop {
+        until: bool,
+        cond: List,
+        body: List,
+    },
+    //words is None for `for name; do`
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    ArithFor {
+        init: Word,
+        cond: Word,
+        step: Word,
+        body: List,
+    },
+    //`{ list; }`
+    Group(List),
+    //`( list )`, with its source text for `jobs`
+    Subshell {
+        body: List,
+        text: String,
+    },
+    //`((expression))`
+    Arith(Word),
+    //`[[ expression ]]`
+    Cond(CondExpr),
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    },
+}
+
+//how a `case` goes on after an item's body has run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseEnd {
+    //`;;`, the case is done
+    Break,
+    //`;&`, the next body runs without its patterns being tried
+    FallThrough,
+    //`;;&`, the patterns of the following items are tried too
+    Continue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub end: CaseEnd,
+}
+
+//the expression in a `[[ ... ]]`, operators are kept as their text
+#[derive(Debug, Clone, PartialEq)]
+pub enum CondExpr {
+    //a lone word, true if it isn't empty
+    Word(Word),
+    Unary(String, Word),
+    Binary(Word, String, Word),
+    Not(Box<CondExpr>),
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+    FunctionDef {
+        name: String,
+        body: Rc<Command>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    //`! cmd` inverts the exit status
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+//pipelines joined by `&&` and `||`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    //ended by `&`
+    pub background: bool,
+    //the source text, shown by `jobs`
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+//the parts of a word with the first n bytes of its leading literal cut off
+fn cut_literal(word: &Word, first: &str, n: usize) -> Vec<WordPart> {
+    let mut parts: Vec<WordPart> = Vec::new();
+    if first.len() > n {
+        parts.push(WordPart::Literal(first[n..].to_string()));
+    }
+    parts.extend(word.parts[1..].iter().cloned());
+    parts
+}
+
+//splits `[subscript]=value` or `[subscript]+=value` at the first unquoted `]=` or `]+=`,
+//returns the subscript, the value and whether it appends
+pub fn split_subscript(word: &Word) -> Option<(Word, Word, bool)> {
+    let first = match word.parts.first() {
+        Some(WordPart::Literal(s)) if s.starts_with('[') => s,
+        _ => return None,
+    };
+    let parts = cut_literal(word, first, 1);
+    for (i, part) in parts.iter().enumerate() {
+        let s = match part {
+            WordPart::Literal(s) => s,
+            _ => continue,
+        };
+        for (pos, _) in s.match_indices(']') {
+            let append = s[pos + 1..].starts_with("+=");
+            if !append && !s[pos + 1..].starts_with('=') {
+                continue;
+            }
+            let mut index = parts[..i].to_vec();
+            let mut value: Vec<WordPart> = Vec::new();
+            if pos > 0 {
+                index.push(WordPart::Literal(s[..pos].to_string()));
+            }
+            let rest = &s[pos + if append { 3 } else { 2 }..];
+            if !rest.is_empty() {
+                value.push(WordPart::Literal(rest.to_string()));
+            }
+            value.extend(parts[i + 1..].iter().cloned());
+            return Some((Word { parts: index }, Word { parts: value }, append));
+        }
+    }
+    None
+}
+
+//splits `name=value`, `name+=value` and `name[subscript]=value` into their parts,
+//the name has to be unquoted
+fn split_assignment(word: &Word) -> Option<Assign> {
+    let first = match word.parts.first() {
+        Some(WordPart::Literal(s)) => s,
+        _ => return None,
+    };
+    let name_len = first.find(|c: char| !c.is_alphanumeric() && c != '_')?;
+    let name = &first[..name_len];
+    if !is_name(name) {
+        return None;
+    }
+    let rest = &first[name_len..];
+    if rest.starts_with('[') {
+        let (index, value, append) = split_subscript(&Word { parts: cut_literal(word, first, name_len) })?;
+        return Some(Assign { name: name.to_string(), index: Some(index), value, append, array: None });
+    }
+    let append = rest.starts_with("+=");
+    if !append && !rest.starts_with('=') {
+        return None;
+    }
+    let parts = cut_literal(word, first, name_len + if append { 2 } else { 1 });
+    Some(Assign { name: name.to_string(), index: None, value: Word { parts }, append, array: None })
+}
+
+//the text of a here-document delimiter without its quotes, and whether any of it was quoted,
+//which stops the body from being expanded
+fn heredoc_delimiter(parts: &[WordPart]) -> (String, bool) {
+    let mut text = String::new();
+    let mut quoted = false;
+    for part in parts {
+        match part {
+            WordPart::Literal(s) => text.push_str(s),
+            WordPart::Quoted(s) => {
+                text.push_str(s);
+                quoted = true;
+            }
+            WordPart::DoubleQuoted(parts) => {
+                text.push_str(&heredoc_delimiter(parts).0);
+                quoted = true;
+            }
+            WordPart::Param(name) => {
+                text.push('$');
+                text.push_str(name);
+            }
+            _ => (),
+        }
+    }
+    (text, quoted)
+}
+
+fn redirect_op(op: Op) -> Option<RedirectOp> {
+    match op {
+        Op::Less => Some(RedirectOp::In),
+        Op::Great => Some(RedirectOp::Out),
+        Op::DGreat => Some(RedirectOp::Append),
+        Op::LessAnd => Some(RedirectOp::DupIn),
+        Op::GreatAnd => Some(RedirectOp::DupOut),
+        Op::AndGreat => Some(RedirectOp::OutErr),
+        Op::AndDGreat => Some(RedirectOp::AppendErr),
+        Op::DLess | Op::DLessDash => Some(RedirectOp::HereDoc),
+        Op::TLess => Some(RedirectOp::HereString),
+        _ => None,
+    }
+}
+
+fn is_redirect_start(token: &Token) -> bool {
+    match token {
+        Token::IoNumber(_) => true,
+        Token::Op(op) => redirect_op(*op).is_some(),
+        _ => false,
+    }
+}
+
+//reserved words that end a list rather than start a command
+const TERMINATORS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "}", "esac"];
+
+fn is_command_start(token: &Token) -> bool {
+    match token {
+        Token::Word(word) => !matches!(word.as_literal(), Some(w) if TERMINATORS.contains(&w)),
+        Token::Op(Op::LParen) => true,
+        token => is_redirect_start(token),
+    }
+}
+
+fn unexpected(token: Token) -> ParseError {
+    match token {
+        Token::Eof => ParseError::Incomplete("syntax error: unexpected end of file".to_string()),
+        token => ParseError::Syntax(format!("syntax error near unexpected token `{}'", token)),
+    }
+}
+
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
+    aliases: &'a HashMap<String, String>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(src: &str, aliases: &'a HashMap<String, String>) -> Self {
+        Parser {
+            lexer: Lexer::new(src, aliases),
+            peeked: None,
+            aliases,
+        }
+    }
+
+    fn peek(&mut self) -> Result<&Token, ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next_token()?);
+        }
+        Ok(self.peeked.as_ref().unwrap())
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lexer.next_token(),
+        }
+    }
+
+    //the peeked token if it is a plain unquoted word, for spotting reserved words
+    fn peek_word(&mut self) -> Result<Option<String>, ParseError> {
+        match self.peek()? {
+            Token::Word(word) => Ok(word.as_literal().map(|s| s.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<(), ParseError> {
+        match self.next()? {
+            Token::Word(word) if word.as_literal() == Some(expected) => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while *self.peek()? == Token::Newline {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_list(&mut self) -> Result<List, ParseError> {
+        let mut items: Vec<AndOr> = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            if !is_command_start(self.peek()?) {
+                break;
+            }
+            items.push(self.parse_and_or()?);
+            match self.peek()? {
+                Token::Op(Op::Semi) => {
+                    self.next()?;
+                }
+                Token::Op(Op::Amp) => {
+                    self.next()?;
+                    items.last_mut().unwrap().background = true;
+                }
+                Token::Newline | Token::Eof => (),
+                //the end of a `case` item or a subshell
+                Token::Op(Op::DSemi) | Token::Op(Op::SemiAnd) | Token::Op(Op::DSemiAnd) | Token::Op(Op::RParen) => break,
+                _ => {
+                    let token = self.next()?;
+                    return Err(unexpected(token));
+                }
+            }
+        }
+        Ok(List { items })
+    }
+
+    //a list that has to contain at least one command, like the body of a loop
+    fn parse_compound_list(&mut self) -> Result<List, ParseError> {
+        let list = self.parse_list()?;
+        if list.items.is_empty() {
+            let token = self.next()?;
+            return Err(unexpected(token));
+        }
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        //the first token has already been peeked
+        let start = self.lexer.token_start;
+        let first = self.parse_pipeline()?;
+        let mut rest: Vec<(Connector, Pipeline)> = Vec::new();
+        loop {
+            let connector = match self.peek()? {
+                Token::Op(Op::AndIf) => Connector::And,
+                Token::Op(Op::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.next()?;
+            self.skip_newlines()?;
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        self.peek()?;
+        let text = self.lexer.text(start, self.lexer.token_start).trim_end().to_string();
+        Ok(AndOr { first, rest, background: false, text })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek_word()?.as_deref() == Some("!");
+        if negated {
+            self.next()?;
+        }
+        let mut commands = vec![self.parse_command()?];
+        while *self.peek()? == Token::Op(Op::Pipe) {
+            self.next()?;
+            self.skip_newlines()?;
+            commands.push(self.parse_command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        match self.peek_word()?.as_deref() {
+            Some("function") => self.parse_function(),
+            Some("if") | Some("while") | Some("until") | Some("for") | Some("{") | Some("[[") | Some("case") => self.parse_compound_command(),
+            _ => match self.peek()? {
+                Token::Op(Op::LParen) => self.parse_compound_command(),
+                token if is_command_start(token) => self.parse_simple_command(),
+                _ => {
+                    let token = self.next()?;
+                    Err(unexpected(token))
+                }
+            },
+        }
+    }
+
+    fn parse_compound_command(&mut self) -> Result<Command, ParseError> {
+        let compound = match self.peek_word()?.as_deref() {
+            Some("if") => self.parse_if()?,
+            Some("while") | Some("until") => self.parse_loop()?,
+            Some("for") => self.parse_for()?,
+            Some("case") => self.parse_case()?,
+            Some("[[") => {
+                self.next()?;
+                let expr = self.parse_cond_or()?;
+                self.expect_word("]]")?;
+                CompoundCommand::Cond(expr)
+            }
+            Some("{") => {
+                self.next()?;
+                let list = self.parse_compound_list()?;
+                self.expect_word("}")?;
+                CompoundCommand::Group(list)
+            }
+            _ if *self.peek()? == Token::Op(Op::LParen) => self.parse_arith_command()?,
+            _ => {
+                let token = self.next()?;
+                return Err(unexpected(token));
+            }
+        };
+        let mut redirects: Vec<Redirect> = Vec::new();
+        while is_redirect_start(self.peek()?) {
+            redirects.push(self.parse_redirect()?);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    //`((expression))` or a subshell
+    fn parse_arith_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        //the `(` has been read already, a second one right after it makes `((`
+        let start = self.lexer.token_start;
+        if let Some(text) = self.lexer.read_arith_rest()? {
+            self.peeked = None;
+            return Ok(CompoundCommand::Arith(lexer::parse_arith_text(&text, self.aliases)?));
+        }
+        self.next()?;
+        let body = self.parse_compound_list()?;
+        self.expect_op(Op::RParen)?;
+        let text = self.lexer.text(start, self.lexer.token_start + 1);
+        Ok(CompoundCommand::Subshell { body, text })
+    }
+
+    fn parse_cond_or(&mut self) -> Result<CondExpr, ParseError> {
+        let mut left = self.parse_cond_and()?;
+        while *self.peek()? == Token::Op(Op::OrIf) {
+            self.next()?;
+            let right = self.parse_cond_and()?;
+            left = CondExpr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_cond_and(&mut self) -> Result<CondExpr, ParseError> {
+        let mut left = self.parse_cond_not()?;
+        while *self.peek()? == Token::Op(Op::AndIf) {
+            self.next()?;
+            let right = self.parse_cond_not()?;
+            left = CondExpr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_cond_not(&mut self) -> Result<CondExpr, ParseError> {
+        self.skip_newlines()?;
+        if self.peek_word()?.as_deref() == Some("!") {
+            self.next()?;
+            return Ok(CondExpr::Not(Box::new(self.parse_cond_not()?)));
+        }
+        self.parse_cond_primary()
+    }
+
+    //`( expr )`, `-op word`, `word op word` or a lone word, `<` and `>` compare strings here
+    fn parse_cond_primary(&mut self) -> Result<CondExpr, ParseError> {
+        let word = match self.next()? {
+            Token::Op(Op::LParen) => {
+                let expr = self.parse_cond_or()?;
+                self.skip_newlines()?;
+                self.expect_op(Op::RParen)?;
+                return Ok(expr);
+            }
+            Token::Word(word) if word.as_literal() != Some("]]") => word,
+            token => return Err(unexpected(token)),
+        };
+        if let Some(op) = word.as_literal().filter(|op| cond::UNARY_OPS.contains(op)) {
+            return match self.next()? {
+                Token::Word(operand) if operand.as_literal() != Some("]]") => Ok(CondExpr::Unary(op.to_string(), operand)),
+                token => Err(ParseError::Syntax(format!("unexpected argument `{}' to conditional unary operator", token))),
+            };
+        }
+        let op = match self.peek()? {
+            Token::Op(Op::Less) => "<".to_string(),
+            Token::Op(Op::Great) => ">".to_string(),
+            Token::Word(op) => match op.as_literal() {
+                Some(op) if op == "=~" || cond::BINARY_OPS.contains(&op) => op.to_string(),
+                _ => return Ok(CondExpr::Word(word)),
+            },
+            _ => return Ok(CondExpr::Word(word)),
+        };
+        self.next()?;
+        let right = if op == "=~" {
+            self.lexer.read_regex()?
+        } else {
+            match self.next()? {
+                Token::Word(right) if right.as_literal() != Some("]]") => right,
+                token => return Err(ParseError::Syntax(format!("unexpected argument `{}' to conditional binary operator", token))),
+            }
+        };
+        Ok(CondExpr::Binary(word, op, right))
+    }
+
+    //`function name [()] body`
+    fn parse_function(&mut self) -> Result<Command, ParseError> {
+        self.expect_word("function")?;
+        let name = match self.next()? {
+            Token::Word(word) if word.as_literal().is_some() => word.as_literal().unwrap().to_string(),
+            token => return Err(unexpected(token)),
+        };
+        if self.lexer.next_char_is('(') {
+            self.expect_op(Op::LParen)?;
+            self.expect_op(Op::RParen)?;
+        }
+        self.parse_function_body(name)
+    }
+
+    fn parse_function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines()?;
+        let body = self.parse_compound_command()?;
+        Ok(Command::FunctionDef { name, body: Rc::new(body) })
+    }
+
+    fn expect_op(&mut self, expected: Op) -> Result<(), ParseError> {
+        match self.next()? {
+            Token::Op(op) if op == expected => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn parse_if(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_word("if")?;
+        let mut branches: Vec<(List, List)> = Vec::new();
+        let mut else_branch: Option<List> = None;
+        loop {
+            let cond = self.parse_compound_list()?;
+            self.expect_word("then")?;
+            let body = self.parse_compound_list()?;
+            branches.push((cond, body));
+            match self.peek_word()?.as_deref() {
+                Some("elif") => {
+                    self.next()?;
+                }
+                Some("else") => {
+                    self.next()?;
+                    else_branch = Some(self.parse_compound_list()?);
+                    self.expect_word("fi")?;
+                    break;
+                }
+                _ => {
+                    self.expect_word("fi")?;
+                    break;
+                }
+            }
+        }
+        Ok(CompoundCommand::If { branches, else_branch })
+    }
+
+    fn parse_loop(&mut self) -> Result<CompoundCommand, ParseError> {
+        let until = self.peek_word()?.as_deref() == Some("until");
+        self.next()?;
+        let cond = self.parse_compound_list()?;
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::Loop { until, cond, body })
+    }
+
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_word("do")?;
+        let body = self.parse_compound_list()?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+
+    fn parse_for(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_word("for")?;
+        if let Some(text) = self.lexer.read_double_paren()? {
+            let mut clauses: Vec<Word> = Vec::new();
+            for clause in text.split(';') {
+                clauses.push(lexer::parse_arith_text(clause, self.aliases)?);
+            }
+            if clauses.len() != 3 {
+                return Err(ParseError::Syntax(format!("(({})): syntax error: expected 3 clauses", text)));
+            }
+            if *self.peek()? == Token::Op(Op::Semi) {
+                self.next()?;
+            }
+            self.skip_newlines()?;
+            let body = self.parse_do_group()?;
+            let step = clauses.pop().unwrap();
+            let cond = clauses.pop().unwrap();
+            let init = clauses.pop().unwrap();
+            return Ok(CompoundCommand::ArithFor { init, cond, step, body });
+        }
+
+        let name = match self.next()? {
+            Token::Word(word) if word.as_literal().is_some_and(is_name) => word.as_literal().unwrap().to_string(),
+            token => return Err(unexpected(token)),
+        };
+        self.skip_newlines()?;
+        let mut words: Option<Vec<Word>> = None;
+        if self.peek_word()?.as_deref() == Some("in") {
+            self.next()?;
+            let mut list: Vec<Word> = Vec::new();
+            while let Token::Word(_) = self.peek()? {
+                match self.next()? {
+                    Token::Word(word) => list.push(word),
+                    _ => unreachable!(),
+                }
+            }
+            words = Some(list);
+        }
+        match self.peek()? {
+            Token::Op(Op::Semi) | Token::Newline => {
+                self.next()?;
+            }
+            _ => (),
+        }
+        self.skip_newlines()?;
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::For { name, words, body })
+    }
+
+    //`case word in [(]pattern [| pattern]...) list ;; ... esac`
+    fn parse_case(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_word("case")?;
+        let word = match self.next()? {
+            Token::Word(word) => word,
+            token => return Err(unexpected(token)),
+        };
+        self.skip_newlines()?;
+        self.expect_word("in")?;
+        let mut items: Vec<CaseItem> = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            if self.peek_word()?.as_deref() == Some("esac") {
+                self.next()?;
+                break;
+            }
+            if *self.peek()? == Token::Op(Op::LParen) {
+                self.next()?;
+            }
+            let mut patterns: Vec<Word> = Vec::new();
+            loop {
+                match self.next()? {
+                    Token::Word(pattern) => patterns.push(pattern),
+                    token => return Err(unexpected(token)),
+                }
+                match self.next()? {
+                    Token::Op(Op::Pipe) => (),
+                    Token::Op(Op::RParen) => break,
+                    token => return Err(unexpected(token)),
+                }
+            }
+            let body = self.parse_list()?;
+            let end = match self.peek()? {
+                Token::Op(Op::DSemi) => CaseEnd::Break,
+                Token::Op(Op::SemiAnd) => CaseEnd::FallThrough,
+                Token::Op(Op::DSemiAnd) => CaseEnd::Continue,
+                //the last item doesn't need a terminator
+                Token::Word(word) if word.as_literal() == Some("esac") => {
+                    items.push(CaseItem { patterns, body, end: CaseEnd::Break });
+                    continue;
+                }
+                _ => {
+                    let token = self.next()?;
+                    return Err(unexpected(token));
+                }
+            };
+            self.next()?;
+            items.push(CaseItem { patterns, body, end });
+        }
+        Ok(CompoundCommand::Case { word, items })
+    }
+
+    //the elements of an array literal, the `(` comes next
+    fn parse_array(&mut self) -> Result<Vec<Word>, ParseError> {
+        self.expect_op(Op::LParen)?;
+        let mut elements: Vec<Word> = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            match self.next()? {
+                Token::Word(word) => elements.push(word),
+                Token::Op(Op::RParen) => return Ok(elements),
+                token => return Err(unexpected(token)),
+            }
+        }
+    }
+
+    //`name=(` and `name+=(` start an array literal
+    fn parse_array_assignment(&mut self, word: &Word) -> Result<Option<Assign>, ParseError> {
+        let mut assign = match split_assignment(word) {
+            Some(assign) => assign,
+            None => return Ok(None),
+        };
+        if assign.index.is_none() && assign.value.parts.is_empty() && self.lexer.next_char_is('(') {
+            assign.array = Some(self.parse_array()?);
+        }
+        Ok(Some(assign))
+    }
+
+    fn parse_simple_command(&mut self) -> Result<Command, ParseError> {
+        let mut assigns: Vec<Assign> = Vec::new();
+        let mut words: Vec<Word> = Vec::new();
+        let mut declared: Vec<Assign> = Vec::new();
+        let mut redirects: Vec<Redirect> = Vec::new();
+        //aliases already expanded for this command, so that `alias ls "ls -a"` terminates
+        let mut expanded: Vec<String> = Vec::new();
+        loop {
+            if is_redirect_start(self.peek()?) {
+                redirects.push(self.parse_redirect()?);
+                continue;
+            }
+            let word = match self.peek()? {
+                Token::Word(_) => match self.next()? {
+                    Token::Word(word) => word,
+                    _ => unreachable!(),
+                },
+                _ => break,
+            };
+            if words.is_empty() {
+                if let Some(assign) = self.parse_array_assignment(&word)? {
+                    assigns.push(assign);
+                    continue;
+                }
+                //`name() body` defines a function
+                if assigns.is_empty() && redirects.is_empty() && self.lexer.next_char_is('(') {
+                    if let Some(name) = word.as_literal().filter(|name| !self.aliases.contains_key(*name)) {
+                        let name = name.to_string();
+                        self.expect_op(Op::LParen)?;
+                        self.expect_op(Op::RParen)?;
+                        return self.parse_function_body(name);
+                    }
+                }
+                if let Some(name) = word.as_literal() {
+                    if let Some(value) = self.aliases.get(name) {
+                        if !expanded.iter().any(|n| n == name) {
+                            expanded.push(name.to_string());
+                            self.lexer.insert(&format!("{} ", value));
+                            continue;
+                        }
+                    }
+                }
+            }
+            if matches!(words.first().and_then(|w| w.as_literal()), Some("declare") | Some("local")) {
+                if let Some(assign) = self.parse_array_assignment(&word)?.filter(|assign| assign.array.is_some()) {
+                    //the builtin only gets the name
+                    words.push(Word { parts: vec![WordPart::Literal(assign.name.clone())] });
+                    declared.push(assign);
+                    continue;
+                }
+            }
+            words.push(word);
+        }
+        Ok(Command::Simple(SimpleCommand { assigns, words, declared, redirects }))
+    }
+
+    fn parse_redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self.peek()? {
+            Token::IoNumber(n) => {
+                let n = *n;
+                self.next()?;
+                Some(n)
+            }
+            _ => None,
+        };
+        let (token_op, op) = match self.next()? {
+            Token::Op(op) if redirect_op(op).is_some() => (op, redirect_op(op).unwrap()),
+            token => return Err(unexpected(token)),
+        };
+        let target = match self.next()? {
+            Token::Word(target) => target,
+            token => return Err(unexpected(token)),
+        };
+        if op == RedirectOp::HereDoc {
+            let (delimiter, quoted) = heredoc_delimiter(&target.parts);
+            let body = self.lexer.read_heredoc(&delimiter, token_op == Op::DLessDash)?;
+            let target = if quoted {
+                Word { parts: vec![WordPart::Quoted(body)] }
+            } else {
+                lexer::parse_heredoc_text(&body, self.aliases)?
+            };
+            return Ok(Redirect { fd, op, target });
+        }
+        Ok(Redirect { fd, op, target })
+    }
+}
+
+pub fn parse(src: &str, aliases: &HashMap<String, String>) -> Result<List, ParseError> {
+    let mut parser = Parser::new(src, aliases);
+    let list = parser.parse_list()?;
+    match parser.next()? {
+        Token::Eof => Ok(list),
+        token => Err(unexpected(token)),
+    }
+}
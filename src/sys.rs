@@ -0,0 +1,238 @@
+//thin wrappers around the libc calls the shell needs
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub enum Fork {
+    Parent(libc::pid_t),
+    Child,
+}
+
+//what waitpid reported about a child
+pub enum WaitEvent {
+    //the raw wait status of a process that exited or was killed
+    Done(libc::c_int),
+    Stopped,
+    Continued,
+}
+
+//signals by name, for `kill` and `trap`
+pub const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+//accepts `TERM`, `SIGTERM` or `15`
+pub fn signal_number(name: &str) -> Option<libc::c_int> {
+    if let Ok(n) = name.parse::<libc::c_int>() {
+        return Some(n);
+    }
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|(_, sig)| *sig)
+}
+
+//the description of a signal, like `Terminated`
+pub fn signal_name(sig: libc::c_int) -> Option<&'static str> {
+    SIGNALS.iter().find(|(_, s)| *s == sig).map(|(name, _)| *name)
+}
+
+pub fn signal_description(sig: libc::c_int) -> String {
+    unsafe { CStr::from_ptr(libc::strsignal(sig)) }.to_string_lossy().into_owned()
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+pub fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds: [libc::c_int; 2] = [0; 2];
+    check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    Ok((fds[0], fds[1]))
+}
+
+pub fn fork() -> io::Result<Fork> {
+    //anything still buffered would otherwise get written twice
+    io::stdout().flush()?;
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(Fork::Child),
+        pid => Ok(Fork::Parent(pid)),
+    }
+}
+
+pub fn dup2(old: RawFd, new: RawFd) -> io::Result<()> {
+    check(unsafe { libc::dup2(old, new) }).map(|_| ())
+}
+
+//copies fd somewhere out of the way of the ones commands use, closed on exec
+pub fn dup_saved(fd: RawFd) -> io::Result<RawFd> {
+    check(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) })
+}
+
+pub fn close(fd: RawFd) {
+    unsafe { libc::close(fd) };
+}
+
+pub fn getpid() -> libc::pid_t {
+    unsafe { libc::getpid() }
+}
+
+pub fn setpgid(pid: libc::pid_t, pgid: libc::pid_t) {
+    unsafe { libc::setpgid(pid, pgid) };
+}
+
+pub fn getpgrp() -> libc::pid_t {
+    unsafe { libc::getpgrp() }
+}
+
+//hands the terminal on fd to a process group
+pub fn tcsetpgrp(fd: RawFd, pgid: libc::pid_t) {
+    unsafe { libc::tcsetpgrp(fd, pgid) };
+}
+
+pub fn tcgetpgrp(fd: RawFd) -> libc::pid_t {
+    unsafe { libc::tcgetpgrp(fd) }
+}
+
+pub fn get_termios(fd: RawFd) -> Option<libc::termios> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    match unsafe { libc::tcgetattr(fd, &mut termios) } {
+        0 => Some(termios),
+        _ => None,
+    }
+}
+
+pub fn set_termios(fd: RawFd, termios: &libc::termios) {
+    unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, termios) };
+}
+
+//whether the shell may read, write or execute path, mode is R_OK, W_OK or X_OK
+pub fn access(path: &str, mode: libc::c_int) -> bool {
+    match std::ffi::CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+pub fn isatty(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+pub fn kill(pid: libc::pid_t, sig: libc::c_int) -> io::Result<()> {
+    check(unsafe { libc::kill(pid, sig) }).map(|_| ())
+}
+
+//signals that have arrived but not been handled yet, set from the signal handler
+static PENDING: [AtomicBool; 65] = [const { AtomicBool::new(false) }; 65];
+
+pub extern "C" fn record_signal(sig: libc::c_int) {
+    if let Some(pending) = PENDING.get(sig as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+//the signals that arrived since the last call
+pub fn take_pending() -> Vec<libc::c_int> {
+    (1..PENDING.len() as libc::c_int)
+        .filter(|sig| PENDING[*sig as usize].swap(false, Ordering::SeqCst))
+        .collect()
+}
+
+pub fn catch_signal(sig: libc::c_int) {
+    unsafe { libc::signal(sig, record_signal as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+}
+
+pub fn ignore_signal(sig: libc::c_int) {
+    unsafe { libc::signal(sig, libc::SIG_IGN) };
+}
+
+pub fn default_signal(sig: libc::c_int) {
+    unsafe { libc::signal(sig, libc::SIG_DFL) };
+}
+
+//puts every signal back to its default in a child, apart from the ignored ones,
+//only async-signal-safe calls so it can run before exec
+pub fn reset_signals(ignored: &[libc::c_int]) {
+    for (_, sig) in SIGNALS {
+        if *sig == libc::SIGKILL || *sig == libc::SIGSTOP {
+            continue;
+        }
+        let handler = if ignored.contains(sig) { libc::SIG_IGN } else { libc::SIG_DFL };
+        unsafe { libc::signal(*sig, handler) };
+    }
+}
+
+//turns a raw wait status into a shell exit status
+pub fn decode_status(status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        0
+    }
+}
+
+//waits for a change in pid, with WUNTRACED also reporting stops, returns None if WNOHANG
+//was given and nothing has happened yet
+pub fn wait_event(pid: libc::pid_t, flags: libc::c_int) -> io::Result<Option<WaitEvent>> {
+    let mut status: libc::c_int = 0;
+    loop {
+        match check(unsafe { libc::waitpid(pid, &mut status, flags) }) {
+            Ok(0) => return Ok(None),
+            Ok(_) if libc::WIFSTOPPED(status) => return Ok(Some(WaitEvent::Stopped)),
+            Ok(_) if libc::WIFCONTINUED(status) => return Ok(Some(WaitEvent::Continued)),
+            Ok(_) => return Ok(Some(WaitEvent::Done(status))),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+//the plain strerror text, without the ` (os error N)` std adds
+pub fn error_message(e: &io::Error) -> String {
+    match e.raw_os_error() {
+        Some(code) => unsafe { CStr::from_ptr(libc::strerror(code)) }.to_string_lossy().into_owned(),
+        None => e.to_string(),
+    }
+}
+
+//leaves a forked child without running the parent's destructors
+pub fn exit(status: i32) -> ! {
+    io::stdout().flush().ok();
+    io::stderr().flush().ok();
+    unsafe { libc::_exit(status) }
+}
@@ -0,0 +1,163 @@
+//signal dispositions and the `trap` builtin
+use crate::sys;
+use crate::{parser, write_out, Shell};
+
+//traps that are not signals
+const PSEUDO_SIGNALS: &[&str] = &["EXIT", "ERR", "DEBUG"];
+
+//the name a trap is stored under, `SIGINT`, `int` and `2` all become `INT`
+fn trap_name(spec: &str) -> Option<String> {
+    let upper = spec.to_ascii_uppercase();
+    if spec == "0" || upper == "EXIT" {
+        return Some("EXIT".to_string());
+    }
+    if PSEUDO_SIGNALS.contains(&upper.as_str()) {
+        return Some(upper);
+    }
+    let sig = sys::signal_number(spec)?;
+    if sig == libc::SIGKILL || sig == libc::SIGSTOP {
+        return None;
+    }
+    sys::signal_name(sig).map(|name| name.to_string())
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+impl Shell {
+    //the interactive shell ignores signals meant for the job in the foreground,
+    //ctrl-c only interrupts whatever the shell itself is running
+    pub fn init_signals(&mut self) {
+        for sig in [libc::SIGQUIT, libc::SIGTERM, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+            sys::ignore_signal(sig);
+        }
+        sys::catch_signal(libc::SIGINT);
+    }
+
+    //how a signal is handled when there is no trap for it
+    fn default_disposition(&self, sig: libc::c_int) {
+        match sig {
+            libc::SIGINT if self.job_control => sys::catch_signal(sig),
+            libc::SIGQUIT | libc::SIGTERM | libc::SIGTSTP | libc::SIGTTIN | libc::SIGTTOU if self.job_control => sys::ignore_signal(sig),
+            //writes to a closed pipe are reported as errors instead
+            libc::SIGPIPE => sys::ignore_signal(sig),
+            sig => sys::default_signal(sig),
+        }
+    }
+
+    //signals ignored with `trap '' SIG`, which stay ignored in commands the shell runs
+    pub fn ignored_signals(&self) -> Vec<libc::c_int> {
+        self.traps.iter()
+            .filter(|(_, action)| action.is_empty())
+            .filter_map(|(name, _)| sys::signal_number(name))
+            .collect()
+    }
+
+    //a forked shell doesn't keep the traps of its parent, only the ignored signals
+    pub fn reset_traps(&mut self) {
+        self.traps.retain(|name, action| action.is_empty() && !PSEUDO_SIGNALS.contains(&name.as_str()));
+        sys::reset_signals(&self.ignored_signals());
+    }
+
+    //runs a trap's commands, leaving $? as it was unless the trap exits
+    fn run_trap(&mut self, name: &str) {
+        let action = match self.traps.get(name) {
+            Some(action) if !action.is_empty() && !self.in_trap => action.clone(),
+            _ => return,
+        };
+        let status = self.last_status;
+        self.in_trap = true;
+        match parser::parse(&action, &self.aliases) {
+            Ok(list) => self.run_list(&list),
+            Err(e) => eprintln!("josh: trap: {}", e),
+        }
+        self.in_trap = false;
+        if !self.exiting {
+            self.last_status = status;
+        }
+    }
+
+    //handles the signals that arrived since the last check, called between commands
+    pub fn check_signals(&mut self) {
+        for sig in sys::take_pending() {
+            let name = sys::signal_name(sig).unwrap_or_default();
+            if self.traps.contains_key(name) {
+                self.run_trap(name);
+            } else if sig == libc::SIGINT {
+                self.interrupted = true;
+            }
+        }
+    }
+
+    //ERR runs when a command fails outside of a condition
+    pub fn run_err_trap(&mut self) {
+        if self.condition_depth == 0 && self.locals.is_empty() && !self.exiting {
+            self.run_trap("ERR");
+        }
+    }
+
+    pub fn run_debug_trap(&mut self) {
+        self.run_trap("DEBUG");
+    }
+
+    //runs the EXIT trap once, as the shell exits
+    pub fn run_exit_trap(&mut self) {
+        if self.traps.contains_key("EXIT") {
+            self.exiting = false;
+            self.run_trap("EXIT");
+            self.traps.remove("EXIT");
+        }
+    }
+
+    pub fn trap_builtin(&mut self, argv: &[String]) -> i32 {
+        let argv: Vec<&String> = argv.iter().skip_while(|arg| *arg == "--").collect();
+        match argv.first().map(|arg| arg.as_str()) {
+            None | Some("-p") => {
+                let mut names: Vec<&String> = self.traps.keys().collect();
+                names.sort();
+                let text: String = names.iter().map(|name| format!("trap -- {} {}\n", quote(&self.traps[*name]), name)).collect();
+                return write_out("trap", &text);
+            }
+            Some("-l") => {
+                let text: String = sys::SIGNALS.iter().map(|(name, number)| format!("{:2}) SIG{}\n", number, name)).collect();
+                return write_out("trap", &text);
+            }
+            _ => (),
+        }
+        //`trap SIG...` with no action resets them like `trap - SIG...`
+        let (action, specs) = if argv.len() == 1 || argv[0].parse::<u32>().is_ok() {
+            ("-", &argv[..])
+        } else {
+            (argv[0].as_str(), &argv[1..])
+        };
+        let mut status = 0;
+        for spec in specs {
+            let name = match trap_name(spec) {
+                Some(name) => name,
+                None => {
+                    eprintln!("josh: trap: {}: invalid signal specification", spec);
+                    status = 1;
+                    continue;
+                }
+            };
+            let sig = sys::signal_number(&name);
+            if action == "-" {
+                self.traps.remove(&name);
+                if let Some(sig) = sig {
+                    self.default_disposition(sig);
+                }
+                continue;
+            }
+            if let Some(sig) = sig {
+                if action.is_empty() {
+                    sys::ignore_signal(sig);
+                } else {
+                    sys::catch_signal(sig);
+                }
+            }
+            self.traps.insert(name, action.to_string());
+        }
+        status
+    }
+}
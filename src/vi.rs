@@ -0,0 +1,417 @@
+//vi editing mode for the prompt, turned on with `set -o vi`
+use std::io::Write;
+
+use termion::event::Key;
+
+//puts the cursor back to the terminal's own shape once a line is done
+pub const DEFAULT_CURSOR: &str = "\x1b[0 q";
+
+//counts are cut down to this, so a long one can't overflow or fill the memory with `p`
+const MAX_COUNT: usize = 9999;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Insert,
+    Normal,
+}
+
+//what the line editor does after a key has gone through vi mode
+pub enum ViResult {
+    Handled,
+    //the key is handled as it is in emacs mode, which keeps Enter, Tab and the history keys
+    Pass(Key),
+    //`v`, the line is edited in $VISUAL or $EDITOR and then run
+    Editor,
+}
+
+//characters are blanks, word characters or punctuation, for W, B and E only blanks are apart
+fn class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+//where the next word starts, or the end of the line
+fn next_word(line: &[char], pos: usize, big: bool) -> usize {
+    let mut i = pos;
+    if let Some(&c) = line.get(i) {
+        let start = class(c, big);
+        while start != 0 && i < line.len() && class(line[i], big) == start { i += 1; }
+    }
+    while i < line.len() && class(line[i], big) == 0 { i += 1; }
+    i
+}
+
+//the last character of the word after pos, or of the one pos is in if it isn't at its end
+fn word_end(line: &[char], pos: usize, big: bool) -> usize {
+    let mut i = pos + 1;
+    while i < line.len() && class(line[i], big) == 0 { i += 1; }
+    if i >= line.len() {
+        return line.len().saturating_sub(1);
+    }
+    let start = class(line[i], big);
+    while i + 1 < line.len() && class(line[i + 1], big) == start { i += 1; }
+    i
+}
+
+fn prev_word(line: &[char], pos: usize, big: bool) -> usize {
+    let mut i = pos;
+    while i > 0 && class(line[i - 1], big) == 0 { i -= 1; }
+    if i > 0 {
+        let start = class(line[i - 1], big);
+        while i > 0 && class(line[i - 1], big) == start { i -= 1; }
+    }
+    i
+}
+
+//the count-th c after pos for f and t, or before it for F and T
+fn find_char(line: &[char], pos: usize, find: char, c: char, count: usize) -> Option<usize> {
+    let found = if find == 'f' || find == 't' {
+        line.iter().enumerate().skip(pos + 1).filter(|(_, x)| **x == c).nth(count - 1)?.0
+    } else {
+        line[..pos].iter().enumerate().rev().filter(|(_, x)| **x == c).nth(count - 1)?.0
+    };
+    Some(match find {
+        't' => found - 1,
+        'T' => found + 1,
+        _ => found,
+    })
+}
+
+pub struct Vi {
+    pub mode: Mode,
+    count: Option<usize>,
+    //`d`, `c` or `y` waiting for a motion, with the count typed before it
+    operator: Option<(char, usize)>,
+    //`f`, `F`, `t`, `T` or `r` waiting for a character
+    awaiting: Option<char>,
+    //the text deleted or yanked last, put back by `p` and `P`
+    register: String,
+    //the keys of the change being made and of the last one made, which `.` repeats
+    recording: Vec<Key>,
+    last_change: Vec<Key>,
+    replaying: bool,
+}
+
+impl Vi {
+    pub fn new() -> Self {
+        Vi {
+            mode: Mode::Insert,
+            count: None,
+            operator: None,
+            awaiting: None,
+            register: String::new(),
+            recording: Vec::new(),
+            last_change: Vec::new(),
+            replaying: false,
+        }
+    }
+
+    //every line starts out in insert mode, as if `i` had been typed on an empty line
+    pub fn start_line(&mut self, undo: &mut Vec<(String, usize)>) {
+        self.mode = Mode::Insert;
+        self.reset();
+        self.recording = vec![Key::Char('i')];
+        undo.push((String::new(), 0));
+    }
+
+    fn reset(&mut self) {
+        self.count = None;
+        self.operator = None;
+        self.awaiting = None;
+    }
+
+    //a block cursor in normal mode and a bar while inserting
+    pub fn show_mode(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        match self.mode {
+            Mode::Normal => write!(out, "{}", termion::cursor::SteadyBlock),
+            Mode::Insert => write!(out, "{}", termion::cursor::SteadyBar),
+        }
+    }
+
+    //in normal mode the cursor is on a character rather than after the last one
+    pub fn fix_cursor(&self, line: &str, pos: &mut usize) {
+        if self.mode == Mode::Normal {
+            *pos = (*pos).min(line.chars().count().saturating_sub(1));
+        }
+    }
+
+    pub fn key(&mut self, key: Key, line: &mut String, pos: &mut usize, undo: &mut Vec<(String, usize)>) -> ViResult {
+        let mut chars: Vec<char> = line.chars().collect();
+        let result = self.dispatch(key, &mut chars, pos, undo);
+        *line = chars.into_iter().collect();
+        self.fix_cursor(line, pos);
+        result
+    }
+
+    fn dispatch(&mut self, key: Key, line: &mut Vec<char>, pos: &mut usize, undo: &mut Vec<(String, usize)>) -> ViResult {
+        match self.mode {
+            Mode::Insert => self.insert_key(key, line, pos, undo),
+            Mode::Normal => self.normal_key(key, line, pos, undo),
+        }
+    }
+
+    fn record(&mut self, key: Key) {
+        if !self.replaying {
+            self.recording.push(key);
+        }
+    }
+
+    fn finish_change(&mut self) {
+        if !self.replaying {
+            self.last_change = std::mem::take(&mut self.recording);
+        }
+    }
+
+    fn insert_key(&mut self, key: Key, line: &mut Vec<char>, pos: &mut usize, undo: &mut Vec<(String, usize)>) -> ViResult {
+        match key {
+            Key::Esc => {
+                self.record(key);
+                self.mode = Mode::Normal;
+                *pos = pos.saturating_sub(1);
+                self.finish_change();
+                ViResult::Handled
+            }
+            //escape typed quickly followed by a key arrives as alt and that key
+            Key::Alt(c) => {
+                self.insert_key(Key::Esc, line, pos, undo);
+                let c = if c == '\r' { '\n' } else { c };
+                self.normal_key(Key::Char(c), line, pos, undo)
+            }
+            Key::Char(c) if c != '\n' && c != '\t' => {
+                self.record(key);
+                line.insert(*pos, c);
+                *pos += 1;
+                ViResult::Handled
+            }
+            Key::Backspace if *pos > 0 => {
+                self.record(key);
+                line.remove(*pos - 1);
+                *pos -= 1;
+                ViResult::Handled
+            }
+            key => ViResult::Pass(key),
+        }
+    }
+
+    fn normal_key(&mut self, key: Key, line: &mut Vec<char>, pos: &mut usize, undo: &mut Vec<(String, usize)>) -> ViResult {
+        let c = match key {
+            Key::Char(c) if c != '\n' && c != '\t' => c,
+            Key::Esc => {
+                self.reset();
+                return ViResult::Handled;
+            }
+            key => {
+                self.reset();
+                return ViResult::Pass(key);
+            }
+        };
+        //a key that starts a new command starts a new recording
+        if !self.replaying && self.count.is_none() && self.operator.is_none() && self.awaiting.is_none() {
+            self.recording.clear();
+        }
+        self.record(key);
+
+        if let Some(awaiting) = self.awaiting.take() {
+            let count = self.count.take().unwrap_or(1);
+            if awaiting == 'r' {
+                if *pos + count <= line.len() {
+                    undo.push((line.iter().collect(), *pos));
+                    for x in &mut line[*pos..*pos + count] {
+                        *x = c;
+                    }
+                    *pos += count - 1;
+                    self.finish_change();
+                }
+                return ViResult::Handled;
+            }
+            return match find_char(line, *pos, awaiting, c, count) {
+                Some(target) => self.apply_motion(target, matches!(awaiting, 'f' | 't'), line, pos, undo),
+                None => {
+                    self.operator = None;
+                    ViResult::Handled
+                }
+            };
+        }
+        match c {
+            '1'..='9' => {
+                let digit = c.to_digit(10).unwrap() as usize;
+                self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit).min(MAX_COUNT));
+                return ViResult::Handled;
+            }
+            '0' if self.count.is_some() => {
+                self.count = Some(self.count.unwrap().saturating_mul(10).min(MAX_COUNT));
+                return ViResult::Handled;
+            }
+            _ => (),
+        }
+        let given = self.count.take();
+        let count = given.unwrap_or(1).saturating_mul(self.operator.map_or(1, |(_, n)| n)).min(MAX_COUNT);
+
+        //`dd`, `cc` and `yy` work on the whole line
+        if let Some((op, _)) = self.operator {
+            if op == c {
+                self.operator = None;
+                return self.operate(op, 0, line.len(), line, pos, undo);
+            }
+        }
+        let len = line.len();
+        let changing = self.operator.map(|(op, _)| op) == Some('c');
+        let motion = match c {
+            'h' => Some((pos.saturating_sub(count), false)),
+            'l' | ' ' => Some(((*pos + count).min(len), false)),
+            //`cw` changes to the end of the word like `ce`
+            'w' | 'W' if changing && line.get(*pos).is_some_and(|x| !x.is_whitespace()) => {
+                let big = c == 'W';
+                let mut end = *pos;
+                for n in 0..count {
+                    if n > 0 || line.get(end + 1).is_some_and(|x| class(*x, big) == class(line[end], big)) {
+                        end = word_end(line, end, big);
+                    }
+                }
+                Some((end, true))
+            }
+            'w' | 'W' => Some(((0..count).fold(*pos, |i, _| next_word(line, i, c == 'W')), false)),
+            'b' | 'B' => Some(((0..count).fold(*pos, |i, _| prev_word(line, i, c == 'B')), false)),
+            'e' | 'E' => Some(((0..count).fold(*pos, |i, _| word_end(line, i, c == 'E')), true)),
+            '0' => Some((0, false)),
+            '^' => Some((line.iter().position(|x| !x.is_whitespace()).unwrap_or(len), false)),
+            '$' if len > 0 => Some((len - 1, true)),
+            '$' => Some((0, false)),
+            'f' | 'F' | 't' | 'T' => {
+                self.awaiting = Some(c);
+                self.count = Some(count);
+                return ViResult::Handled;
+            }
+            _ => None,
+        };
+        if let Some((target, inclusive)) = motion {
+            return self.apply_motion(target, inclusive, line, pos, undo);
+        }
+        //anything but a motion cancels an operator
+        if self.operator.take().is_some() {
+            return ViResult::Handled;
+        }
+        match c {
+            'd' | 'c' | 'y' => self.operator = Some((c, given.unwrap_or(1))),
+            'i' | 'a' | 'I' | 'A' => {
+                undo.push((line.iter().collect(), *pos));
+                *pos = match c {
+                    'i' => *pos,
+                    'a' => (*pos + 1).min(len),
+                    'I' => 0,
+                    _ => len,
+                };
+                self.mode = Mode::Insert;
+            }
+            'x' => return self.operate('d', *pos, (*pos + count).min(len), line, pos, undo),
+            'X' => return self.operate('d', pos.saturating_sub(count), *pos, line, pos, undo),
+            'D' => return self.operate('d', *pos, len, line, pos, undo),
+            'C' => return self.operate('c', *pos, len, line, pos, undo),
+            's' => return self.operate('c', *pos, (*pos + count).min(len), line, pos, undo),
+            'S' => return self.operate('c', 0, len, line, pos, undo),
+            'r' => {
+                self.awaiting = Some('r');
+                self.count = Some(count);
+            }
+            'p' | 'P' if !self.register.is_empty() => {
+                undo.push((line.iter().collect(), *pos));
+                let at = if c == 'p' && len > 0 { *pos + 1 } else { *pos };
+                let text: Vec<char> = self.register.repeat(count).chars().collect();
+                *pos = at + text.len() - 1;
+                line.splice(at..at, text);
+                self.finish_change();
+            }
+            'u' => {
+                if let Some((text, old)) = undo.pop() {
+                    *line = text.chars().collect();
+                    *pos = old;
+                }
+            }
+            '.' => self.repeat(given, line, pos, undo),
+            'k' => return ViResult::Pass(Key::Up),
+            'j' => return ViResult::Pass(Key::Down),
+            'v' => return ViResult::Editor,
+            _ => (),
+        }
+        ViResult::Handled
+    }
+
+    //moves the cursor, or applies a waiting operator from the cursor to target
+    fn apply_motion(&mut self, target: usize, inclusive: bool, line: &mut Vec<char>, pos: &mut usize, undo: &mut Vec<(String, usize)>) -> ViResult {
+        match self.operator.take() {
+            None => {
+                *pos = target;
+                ViResult::Handled
+            }
+            Some((op, _)) => {
+                let (start, end) = if target >= *pos {
+                    (*pos, if inclusive { target + 1 } else { target })
+                } else {
+                    (target, *pos)
+                };
+                self.operate(op, start, end.min(line.len()), line, pos, undo)
+            }
+        }
+    }
+
+    fn operate(&mut self, op: char, start: usize, end: usize, line: &mut Vec<char>, pos: &mut usize, undo: &mut Vec<(String, usize)>) -> ViResult {
+        if op == 'y' {
+            self.register = line[start..end].iter().collect();
+            *pos = start;
+            return ViResult::Handled;
+        }
+        if start < end || op == 'c' {
+            undo.push((line.iter().collect(), *pos));
+        }
+        if start < end {
+            self.register = line.drain(start..end).collect();
+        }
+        *pos = start;
+        if op == 'c' {
+            self.mode = Mode::Insert;
+        } else {
+            self.finish_change();
+        }
+        ViResult::Handled
+    }
+
+    //`.` runs the keys of the last change again, a count given to it replaces the one it had
+    fn repeat(&mut self, count: Option<usize>, line: &mut Vec<char>, pos: &mut usize, undo: &mut Vec<(String, usize)>) {
+        let mut keys = self.last_change.clone();
+        if let Some(count) = count {
+            let digits = keys.iter().take_while(|key| matches!(key, Key::Char('0'..='9'))).count();
+            keys.splice(..digits, count.to_string().chars().map(Key::Char));
+        }
+        self.replaying = true;
+        for key in keys {
+            self.dispatch(key, line, pos, undo);
+        }
+        self.replaying = false;
+    }
+}
+
+//writes the line to a file for the editor and reads back what it was changed to
+pub fn edit_in_editor<'a>(editor: &str, text: &str, envs: Vec<(&'a String, &'a String)>) -> Option<String> {
+    let path = std::env::temp_dir().join(format!("josh-edit-{}.sh", crate::sys::getpid()));
+    let mut file = std::fs::File::create(&path).ok()?;
+    writeln!(file, "{}", text).ok()?;
+    drop(file);
+    let mut words = editor.split_whitespace();
+    let status = std::process::Command::new(words.next()?)
+        .args(words)
+        .arg(&path)
+        .env_clear()
+        .envs(envs)
+        .status();
+    let edited = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).ok();
+    match (status, edited) {
+        (Ok(status), Ok(edited)) if status.success() => Some(edited.trim_end_matches('\n').to_string()),
+        _ => None,
+    }
+}